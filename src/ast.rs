@@ -1,10 +1,26 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 
+use crate::errors::CompileError;
+use crate::intrinsics;
+use crate::lex::Span;
+use crate::preprocess::SourceMap;
+use crate::runtime;
+
+// Every node records the source it was parsed from, from its first token to its
+// last, and a NodeId. Passes that rewrite the tree, such as module
+// qualification, change nodes in place, so both stay with the node through to
+// code generation.
 pub enum Stm {
     Assign(AssignStm),
     Compound(CompoundStm),
     Print(PrintStm),
     For(ForStm),
+    Import(ImportStm),
+    Export(ExportStm),
+    Asm(AsmStm),
+    Const(ConstStm),
+    Error(ErrorStm),
 }
 
 pub enum Exp {
@@ -13,6 +29,8 @@ pub enum Exp {
     Op(OpExp),
     Eseq(EseqExp),
     Comp(CompExp),
+    Str(StrExp),
+    Call(CallExp),
 }
 
 pub enum ExpList {
@@ -23,15 +41,24 @@ pub enum ExpList {
 pub struct AssignStm {
     pub id: Box<Exp>,
     pub exp: Box<Exp>,
+    pub i64_decl: bool,
+    pub span: Span,
+    pub node_id: NodeId,
 }
 
 pub struct CompoundStm {
     pub stm1: Box<Stm>,
     pub stm2: Box<Stm>,
+    pub span: Span,
+    pub node_id: NodeId,
 }
 
 pub struct PrintStm {
     pub exps: Box<ExpList>,
+    pub newline: bool,
+    pub chars: bool,
+    pub span: Span,
+    pub node_id: NodeId,
 }
 
 pub struct ForStm {
@@ -39,47 +66,467 @@ pub struct ForStm {
     pub comp: Box<Exp>,
     pub inc: Box<Stm>,
     pub body: Box<Stm>,
+    pub span: Span,
+    pub node_id: NodeId,
+}
+
+pub struct ImportStm {
+    pub module: String,
+    pub span: Span,
+    pub node_id: NodeId,
+}
+
+pub struct ExportStm {
+    pub id: String,
+    pub span: Span,
+    pub node_id: NodeId,
+}
+
+// asm("template", out(x), in(exp), clobber("$s0")). The template's %N refers to
+// the N-th out/in operand, counted in the order they are written.
+pub struct AsmStm {
+    pub template: String,
+    pub operands: Vec<AsmOperand>,
+    pub span: Span,
+    pub node_id: NodeId,
+}
+
+// const N := exp; the value is computed by the compiler and N never gets storage.
+pub struct ConstStm {
+    pub id: String,
+    pub exp: Box<Exp>,
+    pub span: Span,
+    pub node_id: NodeId,
+}
+
+// Where the parser recovered from a syntax error; the statement that failed is
+// gone, but the rest of the program around it is kept.
+pub struct ErrorStm {
+    pub span: Span,
+    pub node_id: NodeId,
+}
+
+// Identifies one node of a program. Ids are handed out by a NodeIds in the order
+// the nodes are built, and one NodeIds serves every file of a program, so they
+// are unique across modules and the same each time the same program is loaded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(pub usize);
+
+#[derive(Default)]
+pub struct NodeIds {
+    next: usize,
+}
+
+impl NodeIds {
+    pub fn fresh(&mut self) -> NodeId {
+        self.next += 1;
+        NodeId(self.next)
+    }
+}
+
+// The operands of an AsmStm, which has their span; an out or in operand's
+// expression has its own.
+pub enum AsmOperand {
+    Out(Box<Exp>),
+    In(Box<Exp>),
+    Clobber(String),
 }
 
 pub struct IdExp {
     pub id: String,
+    pub span: Span,
+    pub node_id: NodeId,
 }
 
 pub struct NumExp {
     pub num: String,
+    pub span: Span,
+    pub node_id: NodeId,
+}
+
+pub struct StrExp {
+    pub value: String,
+    pub span: Span,
+    pub node_id: NodeId,
 }
 
 pub struct OpExp {
     pub left: Box<Exp>,
     pub right: Box<Exp>,
     pub op: String,
+    pub span: Span,
+    pub node_id: NodeId,
+}
+
+// A call of one of the built-in functions in intrinsics.rs.
+pub struct CallExp {
+    pub name: String,
+    pub args: Box<ExpList>,
+    pub span: Span,
+    pub node_id: NodeId,
 }
 
 pub struct EseqExp {
     pub stm: Box<Stm>,
     pub exp: Box<Exp>,
+    pub span: Span,
+    pub node_id: NodeId,
 }
 
 pub struct PairExpList {
     pub head: Box<Exp>,
     pub tail: Box<ExpList>,
+    pub span: Span,
+    pub node_id: NodeId,
 }
 
 pub struct LastExpList {
     pub head: Box<Exp>,
+    pub span: Span,
+    pub node_id: NodeId,
 }
 
 pub struct CompExp {
     pub left: Box<Exp>,
     pub right: Box<Exp>,
     pub op: u8,
+    pub span: Span,
+    pub node_id: NodeId,
+}
+
+// Renders a tree back as source, for --trace-parse and --parser=compare.
+// Arithmetic is fully parenthesized so the grouping the parser chose shows; those
+// parentheses aren't valid source, since the language has none for grouping.
+impl fmt::Display for Stm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stm::Assign(assign) => {
+                if assign.i64_decl {
+                    write!(f, "i64 ")?;
+                }
+                write!(f, "{} := {}", assign.id, assign.exp)
+            }
+            Stm::Compound(compound) => write!(f, "{}; {}", compound.stm1, compound.stm2),
+            Stm::Print(print) => {
+                let name = match (print.newline, print.chars) {
+                    (_, true) => "print_char",
+                    (true, false) => "print",
+                    (false, false) => "write",
+                };
+                write!(f, "{}({})", name, print.exps)
+            }
+            Stm::For(for_stm) => write!(
+                f,
+                "for ({}; {}; {}) {{ {} }}",
+                for_stm.iter, for_stm.comp, for_stm.inc, for_stm.body
+            ),
+            Stm::Import(import) => write!(f, "import {}", import.module),
+            Stm::Export(export) => write!(f, "export {}", export.id),
+            Stm::Asm(asm) => {
+                write!(f, "asm(\"{}\"", asm.template)?;
+                for operand in &asm.operands {
+                    write!(f, ", {}", operand)?;
+                }
+                write!(f, ")")
+            }
+            Stm::Const(const_stm) => write!(f, "const {} := {}", const_stm.id, const_stm.exp),
+            Stm::Error(_) => write!(f, "<error>"),
+        }
+    }
+}
+
+impl fmt::Display for Exp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Exp::Id(id) => write!(f, "{}", id.id),
+            Exp::Num(num) => write!(f, "{}", num.num),
+            Exp::Str(str) => write!(f, "\"{}\"", str.value),
+            Exp::Op(op) => {
+                let symbol = match op.op.as_str() {
+                    "add" => "+",
+                    "sub" => "-",
+                    "mul" => "*",
+                    "div" => "/",
+                    other => other,
+                };
+                write!(f, "({} {} {})", op.left, symbol, op.right)
+            }
+            Exp::Eseq(eseq) => write!(f, "({}, {})", eseq.stm, eseq.exp),
+            Exp::Comp(comp) => {
+                let symbol = if comp.op == 0 { "<" } else { ">" };
+                write!(f, "{} {} {}", comp.left, symbol, comp.right)
+            }
+            Exp::Call(call) => write!(f, "{}({})", call.name, call.args),
+        }
+    }
+}
+
+impl fmt::Display for ExpList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let exps: Vec<String> = self.exps().iter().map(|exp| exp.to_string()).collect();
+        write!(f, "{}", exps.join(", "))
+    }
+}
+
+impl fmt::Display for AsmOperand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AsmOperand::Out(exp) => write!(f, "out({})", exp),
+            AsmOperand::In(exp) => write!(f, "in({})", exp),
+            AsmOperand::Clobber(reg) => write!(f, "clobber(\"{}\")", reg),
+        }
+    }
+}
+
+// The first place two trees differ, as the two differing subtrees. Node ids are
+// ignored, so trees built by different parsers compare equal when they have the
+// same shape, the same names, numbers and operators, and the same spans.
+pub fn stm_difference(a: &Stm, b: &Stm) -> Option<(String, String)> {
+    stm_shape_difference(a, b).or_else(|| span_difference(a, a.span(), b, b.span()))
+}
+
+fn stm_shape_difference(a: &Stm, b: &Stm) -> Option<(String, String)> {
+    let differ = || Some((a.to_string(), b.to_string()));
+    match (a, b) {
+        (Stm::Assign(x), Stm::Assign(y)) if x.i64_decl == y.i64_decl => {
+            exp_difference(&x.id, &y.id).or_else(|| exp_difference(&x.exp, &y.exp))
+        }
+        (Stm::Compound(x), Stm::Compound(y)) => {
+            stm_difference(&x.stm1, &y.stm1).or_else(|| stm_difference(&x.stm2, &y.stm2))
+        }
+        (Stm::Print(x), Stm::Print(y)) if x.newline == y.newline && x.chars == y.chars => {
+            exp_list_difference(&x.exps, &y.exps)
+        }
+        (Stm::For(x), Stm::For(y)) => stm_difference(&x.iter, &y.iter)
+            .or_else(|| exp_difference(&x.comp, &y.comp))
+            .or_else(|| stm_difference(&x.inc, &y.inc))
+            .or_else(|| stm_difference(&x.body, &y.body)),
+        (Stm::Import(x), Stm::Import(y)) if x.module == y.module => None,
+        (Stm::Export(x), Stm::Export(y)) if x.id == y.id => None,
+        (Stm::Asm(x), Stm::Asm(y))
+            if x.template == y.template && x.operands.len() == y.operands.len() =>
+        {
+            for (x, y) in x.operands.iter().zip(&y.operands) {
+                let found = match (x, y) {
+                    (AsmOperand::Out(x), AsmOperand::Out(y))
+                    | (AsmOperand::In(x), AsmOperand::In(y)) => exp_difference(x, y),
+                    (AsmOperand::Clobber(x), AsmOperand::Clobber(y)) if x == y => None,
+                    _ => differ(),
+                };
+                if found.is_some() {
+                    return found;
+                }
+            }
+            None
+        }
+        (Stm::Const(x), Stm::Const(y)) if x.id == y.id => exp_difference(&x.exp, &y.exp),
+        (Stm::Error(_), Stm::Error(_)) => None,
+        _ => differ(),
+    }
+}
+
+fn exp_difference(a: &Exp, b: &Exp) -> Option<(String, String)> {
+    exp_shape_difference(a, b).or_else(|| span_difference(a, a.span(), b, b.span()))
+}
+
+fn exp_shape_difference(a: &Exp, b: &Exp) -> Option<(String, String)> {
+    match (a, b) {
+        (Exp::Id(x), Exp::Id(y)) if x.id == y.id => None,
+        (Exp::Num(x), Exp::Num(y)) if x.num == y.num => None,
+        (Exp::Str(x), Exp::Str(y)) if x.value == y.value => None,
+        (Exp::Op(x), Exp::Op(y)) if x.op == y.op => {
+            exp_difference(&x.left, &y.left).or_else(|| exp_difference(&x.right, &y.right))
+        }
+        (Exp::Eseq(x), Exp::Eseq(y)) => {
+            stm_difference(&x.stm, &y.stm).or_else(|| exp_difference(&x.exp, &y.exp))
+        }
+        (Exp::Comp(x), Exp::Comp(y)) if x.op == y.op => {
+            exp_difference(&x.left, &y.left).or_else(|| exp_difference(&x.right, &y.right))
+        }
+        (Exp::Call(x), Exp::Call(y)) if x.name == y.name => exp_list_difference(&x.args, &y.args),
+        _ => Some((a.to_string(), b.to_string())),
+    }
+}
+
+fn exp_list_difference(a: &ExpList, b: &ExpList) -> Option<(String, String)> {
+    let (xs, ys) = (a.exps(), b.exps());
+    if xs.len() != ys.len() {
+        return Some((a.to_string(), b.to_string()));
+    }
+    xs.iter()
+        .zip(ys)
+        .find_map(|(x, y)| exp_difference(x, y))
+        .or_else(|| span_difference(a, a.span(), b, b.span()))
+}
+
+// Two subtrees that are the same but for their spans, with the bytes each
+// covers. Checked after their children, so the innermost difference is shown.
+fn span_difference(
+    a: &dyn fmt::Display,
+    a_span: &Span,
+    b: &dyn fmt::Display,
+    b_span: &Span,
+) -> Option<(String, String)> {
+    if a_span == b_span {
+        return None;
+    }
+    Some((
+        format!("{} (bytes {}..{})", a, a_span.start, a_span.end),
+        format!("{} (bytes {}..{})", b, b_span.start, b_span.end),
+    ))
+}
+
+// Collects every variable declared with `i64`. A variable keeps the type it was
+// declared with for the whole program, wherever the declaration appears.
+pub fn collect_i64_vars(stm: &Stm, vars: &mut HashSet<String>) {
+    match stm {
+        Stm::Assign(assign) => {
+            if assign.i64_decl {
+                if let Exp::Id(id) = assign.id.as_ref() {
+                    vars.insert(id.id.clone());
+                }
+            }
+            collect_i64_vars_exp(&assign.exp, vars);
+        }
+        Stm::Compound(compound) => {
+            collect_i64_vars(&compound.stm1, vars);
+            collect_i64_vars(&compound.stm2, vars);
+        }
+        Stm::Print(print) => {
+            for exp in print.exps.exps() {
+                collect_i64_vars_exp(exp, vars);
+            }
+        }
+        Stm::For(for_stm) => {
+            collect_i64_vars(&for_stm.iter, vars);
+            collect_i64_vars_exp(&for_stm.comp, vars);
+            collect_i64_vars(&for_stm.inc, vars);
+            collect_i64_vars(&for_stm.body, vars);
+        }
+        Stm::Asm(asm) => {
+            for operand in &asm.operands {
+                if let AsmOperand::In(exp) = operand {
+                    collect_i64_vars_exp(exp, vars);
+                }
+            }
+        }
+        Stm::Const(const_stm) => collect_i64_vars_exp(&const_stm.exp, vars),
+        Stm::Import(_) | Stm::Export(_) | Stm::Error(_) => {}
+    }
+}
+
+fn collect_i64_vars_exp(exp: &Exp, vars: &mut HashSet<String>) {
+    match exp {
+        Exp::Op(op) => {
+            collect_i64_vars_exp(&op.left, vars);
+            collect_i64_vars_exp(&op.right, vars);
+        }
+        Exp::Eseq(eseq) => {
+            collect_i64_vars(&eseq.stm, vars);
+            collect_i64_vars_exp(&eseq.exp, vars);
+        }
+        Exp::Comp(comp) => {
+            collect_i64_vars_exp(&comp.left, vars);
+            collect_i64_vars_exp(&comp.right, vars);
+        }
+        Exp::Call(call) => {
+            for arg in call.args.exps() {
+                collect_i64_vars_exp(arg, vars);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Splits a template at its \n escapes and resolves the other escapes. String
+// literals keep their escapes as written until they reach .asciiz.
+fn template_lines(template: &str) -> Vec<String> {
+    let mut lines = vec![String::new()];
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        let current = lines.last_mut().unwrap();
+        if c != '\\' {
+            current.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => lines.push(String::new()),
+            Some('t') => current.push('\t'),
+            Some(escaped) => current.push(escaped),
+            None => {}
+        }
+    }
+    lines
+}
+
+// Replaces %N with the register bound to operand N and %% with %. Returns the
+// offending operand if the template refers to one that does not exist.
+fn substitute_operands(line: &str, registers: &[String]) -> Result<String, String> {
+    let mut out = String::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        if chars.peek() == Some(&'%') {
+            chars.next();
+            out.push('%');
+            continue;
+        }
+        let mut digits = String::new();
+        while let Some(d) = chars.peek().filter(|d| d.is_ascii_digit()) {
+            digits.push(*d);
+            chars.next();
+        }
+        match digits.parse::<usize>().ok().and_then(|n| registers.get(n)) {
+            Some(register) => out.push_str(register),
+            None => return Err(format!("%{}", digits)),
+        }
+    }
+    Ok(out)
+}
+
+// A variable the generated code keeps for itself. An identifier in the source
+// always starts with a letter, so the leading underscore keeps this one apart
+// from every variable the program declares.
+fn hidden_var(name: String) -> String {
+    format!("_{}", name)
+}
+
+// An i64 variable lives in two words, one per register of the pair. The
+// halves are hidden variables: a suffix would let `a.lo` name both the low
+// half of `a` and the variable `lo` of module `a`.
+pub fn i64_halves(id: &str) -> (String, String) {
+    (
+        hidden_var(format!("lo.{}", id)),
+        hidden_var(format!("hi.{}", id)),
+    )
+}
+
+// The value of a decimal literal too wide for one word, which only a register
+// pair can hold.
+fn wide_literal(num: &NumExp) -> Option<i64> {
+    match num.num.parse::<i64>() {
+        Ok(value) if i32::try_from(value).is_err() => Some(value),
+        _ => None,
+    }
+}
+
+// Whether an operator's result takes a literal too wide for 32-bit arithmetic.
+fn has_wide_literal(exp: &Exp) -> bool {
+    match exp {
+        Exp::Num(num) => wide_literal(num).is_some(),
+        Exp::Op(op) => has_wide_literal(&op.left) || has_wide_literal(&op.right),
+        Exp::Eseq(eseq) => has_wide_literal(&eseq.exp),
+        _ => false,
+    }
 }
 
 // Define the Visitor trait
 pub trait Visitor {
     fn visit_stm(&mut self, stm: &Stm);
     fn visit_exp(&mut self, exp: &Exp) -> String;
-    fn visit_exp_list(&mut self, exp_list: &ExpList) -> Vec<String>;
 }
 
 // Implement accept methods for each node type
@@ -87,17 +534,101 @@ impl Stm {
     pub fn accept<V: Visitor>(&self, visitor: &mut V) {
         visitor.visit_stm(self);
     }
+
+    pub fn span(&self) -> &Span {
+        match self {
+            Stm::Assign(assign) => &assign.span,
+            Stm::Compound(compound) => &compound.span,
+            Stm::Print(print) => &print.span,
+            Stm::For(for_stm) => &for_stm.span,
+            Stm::Import(import) => &import.span,
+            Stm::Export(export) => &export.span,
+            Stm::Asm(asm) => &asm.span,
+            Stm::Const(const_stm) => &const_stm.span,
+            Stm::Error(error) => &error.span,
+        }
+    }
+
+    pub fn node_id(&self) -> NodeId {
+        match self {
+            Stm::Assign(assign) => assign.node_id,
+            Stm::Compound(compound) => compound.node_id,
+            Stm::Print(print) => print.node_id,
+            Stm::For(for_stm) => for_stm.node_id,
+            Stm::Import(import) => import.node_id,
+            Stm::Export(export) => export.node_id,
+            Stm::Asm(asm) => asm.node_id,
+            Stm::Const(const_stm) => const_stm.node_id,
+            Stm::Error(error) => error.node_id,
+        }
+    }
 }
 
 impl Exp {
     pub fn accept<V: Visitor>(&self, visitor: &mut V) -> String {
         visitor.visit_exp(self)
     }
+
+    pub fn span(&self) -> &Span {
+        match self {
+            Exp::Id(id) => &id.span,
+            Exp::Num(num) => &num.span,
+            Exp::Op(op) => &op.span,
+            Exp::Eseq(eseq) => &eseq.span,
+            Exp::Comp(comp) => &comp.span,
+            Exp::Str(str) => &str.span,
+            Exp::Call(call) => &call.span,
+        }
+    }
+
+    pub fn node_id(&self) -> NodeId {
+        match self {
+            Exp::Id(id) => id.node_id,
+            Exp::Num(num) => num.node_id,
+            Exp::Op(op) => op.node_id,
+            Exp::Eseq(eseq) => eseq.node_id,
+            Exp::Comp(comp) => comp.node_id,
+            Exp::Str(str) => str.node_id,
+            Exp::Call(call) => call.node_id,
+        }
+    }
 }
 
 impl ExpList {
-    pub fn accept<V: Visitor>(&self, visitor: &mut V) -> Vec<String> {
-        visitor.visit_exp_list(self)
+    pub fn span(&self) -> &Span {
+        match self {
+            ExpList::Pair(pair) => &pair.span,
+            ExpList::Last(last) => &last.span,
+        }
+    }
+
+    pub fn node_id(&self) -> NodeId {
+        match self {
+            ExpList::Pair(pair) => pair.node_id,
+            ExpList::Last(last) => last.node_id,
+        }
+    }
+
+    pub fn exps(&self) -> Vec<&Exp> {
+        match self {
+            ExpList::Pair(pair) => {
+                let mut exps = vec![pair.head.as_ref()];
+                exps.append(&mut pair.tail.exps());
+                exps
+            }
+            ExpList::Last(last) => vec![last.head.as_ref()],
+        }
+    }
+
+    pub fn exps_mut(&mut self) -> Vec<&mut Exp> {
+        match self {
+            ExpList::Pair(pair) => {
+                let mut exps = vec![pair.head.as_mut()];
+                exps.append(&mut pair.tail.exps_mut());
+                exps
+            }
+            ExpList::Last(last) => vec![last.head.as_mut()],
+        }
     }
 }
 
@@ -108,6 +639,23 @@ pub struct CodeGenerator {
     pub var_num: u16,
     pub label_num: u16,
     pub var_set: HashSet<String>,
+    pub strings: Vec<String>,
+    pub print_sep: String,
+    pub checked_arith: bool,
+    pub fixed_point: bool,
+    pub runtime: String,
+    pub i64_vars: HashSet<String>,
+    pub errors: Vec<CompileError>,
+    pub source_map: SourceMap,
+    pub call_depth: usize,
+    // 64-bit operators being evaluated, so each parks its left operand apart
+    pub i64_depth: usize,
+    // prints whose arguments are being evaluated, so a print inside an argument
+    // parks its values apart from the print around it
+    pub print_depth: usize,
+    // likewise for asm statements inside an `in` operand
+    pub asm_depth: usize,
+    pub consts: HashMap<String, i32>,
 }
 
 impl CodeGenerator {
@@ -139,8 +687,12 @@ impl CodeGenerator {
         x.parse::<f64>().is_ok()
     }
 
-    fn load_print_instr(&mut self) {
-        self.generated_code.push_str("li $v0, 1\n");
+    fn load_print_instr(&mut self, chars: bool) {
+        if chars {
+            self.generated_code.push_str("li $v0, 11\n");
+        } else {
+            self.generated_code.push_str("li $v0, 1\n");
+        }
     }
 
     fn syscall(&mut self) {
@@ -151,12 +703,702 @@ impl CodeGenerator {
         self.generated_code
             .push_str("li $v0, 4\nla $a0, newline\nsyscall\n");
     }
+
+    fn string_label(&mut self, value: &str) -> String {
+        let index = match self.strings.iter().position(|s| s == value) {
+            Some(index) => index,
+            None => {
+                self.strings.push(value.to_string());
+                self.strings.len() - 1
+            }
+        };
+        let mut label = "STRLABEL".to_string();
+        label.push_str(&index.to_string());
+        label
+    }
+
+    fn print_string_instr(&mut self, label: &str) {
+        self.generated_code.push_str("li $v0, 4\nla $a0, ");
+        self.generated_code.push_str(label);
+        self.generated_code.push_str("\nsyscall\n");
+    }
+
+    fn separator_instr(&mut self) {
+        if !self.print_sep.is_empty() {
+            let sep = self.print_sep.clone();
+            let label = self.string_label(&sep);
+            self.print_string_instr(&label);
+        }
+    }
+
+    // Wrapping mode uses the non-trapping forms; checked mode keeps the trapping
+    // add/sub so the overflow exception handler can report the line.
+    fn arith_instr(&self, op: &str) -> &'static str {
+        match (op, self.checked_arith) {
+            ("add", false) => "addu",
+            ("sub", false) => "subu",
+            ("add", true) => "add",
+            ("sub", true) => "sub",
+            ("mul", _) => "mul",
+            _ => "div",
+        }
+    }
+
+    // $v1 is not used anywhere else, so it holds the source location of the last
+    // checked operation for the overflow handler to print.
+    fn overflow_location(&mut self, span: &Span) {
+        self.require_overflow_handler();
+        let location = self.source_map.locate_span(span);
+        let label = self.string_label(&location);
+        self.generated_code.push_str("la $v1, ");
+        self.generated_code.push_str(&label);
+        self.generated_code.push('\n');
+    }
+
+    // The 64-bit product only fits in 32 bits if HI is the sign extension of LO.
+    fn checked_mul(&mut self, dest: &str, left: String, right: String, span: &Span) {
+        let left = self.operand_register(left);
+        let right = self.operand_register(right);
+        let hi = self.get_temp();
+        let sign = self.get_temp();
+
+        self.overflow_location(span);
+        self.generated_code
+            .push_str(&format!("mult {}, {}\n", left, right));
+        self.generated_code.push_str(&format!("mflo {}\n", dest));
+        self.generated_code.push_str(&format!("mfhi {}\n", hi));
+        self.generated_code
+            .push_str(&format!("sra {}, {}, 31\n", sign, dest));
+        self.generated_code
+            .push_str(&format!("bne {}, {}, OVERFLOW\n", hi, sign));
+    }
+
+    // Literals are scaled to Q16.16 in fixed-point mode; otherwise only integers
+    // have a lowering.
+    fn num_value(&mut self, num: &NumExp) -> String {
+        if !self.fixed_point {
+            if num.num.contains('.') {
+                self.errors.push(CompileError::DecimalLiteral(
+                    num.num.clone(),
+                    num.span.clone(),
+                ));
+            } else if wide_literal(num).is_some() {
+                self.errors
+                    .push(CompileError::WideLiteral(num.num.clone(), num.span.clone()));
+                return String::from("0");
+            }
+            return num.num.clone();
+        }
+        let scaled = (num.num.parse::<f64>().unwrap_or(0.0) * 65536.0).round();
+        if scaled < i32::MIN as f64 || scaled > i32::MAX as f64 {
+            self.errors.push(CompileError::FixedPointRange(
+                num.num.clone(),
+                num.span.clone(),
+            ));
+            return String::from("0");
+        }
+        (scaled as i32).to_string()
+    }
+
+    // The Q32.32 product is in HI:LO; the Q16.16 result is its middle 32 bits.
+    // In checked mode the bits of HI above them must all match its sign.
+    fn fixed_mul(&mut self, dest: &str, left: String, right: String, span: &Span) {
+        let left = self.operand_register(left);
+        let right = self.operand_register(right);
+        let hi = self.get_temp();
+
+        if self.checked_arith {
+            self.overflow_location(span);
+        }
+        self.generated_code
+            .push_str(&format!("mult {}, {}\n", left, right));
+        self.generated_code.push_str(&format!("mflo {}\n", dest));
+        self.generated_code.push_str(&format!("mfhi {}\n", hi));
+        if self.checked_arith {
+            let top = self.get_temp();
+            let sign = self.get_temp();
+            self.generated_code
+                .push_str(&format!("sra {}, {}, 15\n", top, hi));
+            self.generated_code
+                .push_str(&format!("sra {}, {}, 31\n", sign, hi));
+            self.generated_code
+                .push_str(&format!("bne {}, {}, OVERFLOW\n", top, sign));
+        }
+        self.generated_code
+            .push_str(&format!("srl {}, {}, 16\n", dest, dest));
+        self.generated_code
+            .push_str(&format!("sll {}, {}, 16\n", hi, hi));
+        self.generated_code
+            .push_str(&format!("or {}, {}, {}\n", dest, dest, hi));
+    }
+
+    fn fixed_div(&mut self, dest: &str, left: String, right: String) {
+        self.require_routine("FIXDIV", runtime::FIX_DIV);
+        let left = self.operand_register(left);
+        let right = self.operand_register(right);
+        self.generated_code.push_str(&format!(
+            "move $a0, {}\nmove $a1, {}\njal FIXDIV\nmove {}, $v0\n",
+            left, right, dest
+        ));
+    }
+
+    // Variables are only rewritten to registers by the allocator in a few
+    // instruction forms, so copy them into a temp before using them elsewhere.
+    fn operand_register(&mut self, operand: String) -> String {
+        if operand.starts_with('$') {
+            return operand;
+        }
+        let temp = self.get_temp();
+        if self.is_numeric(&operand) {
+            self.generated_code.push_str("li ");
+        } else {
+            self.generated_code.push_str("move ");
+        }
+        self.generated_code.push_str(&temp);
+        self.generated_code.push_str(", ");
+        self.generated_code.push_str(&operand);
+        self.generated_code.push('\n');
+        temp
+    }
+
+    fn is_i64(&self, exp: &Exp) -> bool {
+        match exp {
+            Exp::Id(id) => self.i64_vars.contains(&id.id),
+            Exp::Op(op) => self.is_i64(&op.left) || self.is_i64(&op.right),
+            Exp::Eseq(eseq) => self.is_i64(&eseq.exp),
+            _ => false,
+        }
+    }
+
+    // Evaluates an expression to a 64-bit value in a (lo, hi) register pair.
+    // 32-bit subexpressions are sign extended. The pair registers are always
+    // fresh temps owned by this expression, so operators work on them in place.
+    fn visit_i64_exp(&mut self, exp: &Exp) -> (String, String) {
+        match exp {
+            Exp::Id(id) if self.i64_vars.contains(&id.id) => {
+                let (lo_var, hi_var) = i64_halves(&id.id);
+                self.var_set.insert(lo_var.clone());
+                self.var_set.insert(hi_var.clone());
+                let lo = self.get_temp();
+                let hi = self.get_temp();
+                self.generated_code
+                    .push_str(&format!("move {}, {}\n", lo, lo_var));
+                self.generated_code
+                    .push_str(&format!("move {}, {}\n", hi, hi_var));
+                (lo, hi)
+            }
+            Exp::Op(op) if self.is_i64(exp) || has_wide_literal(exp) => {
+                let (mut a_lo, mut a_hi) = self.visit_i64_exp(&op.left);
+                // beyond a name or number, the right operand can cycle through the
+                // temps and overwrite the left pair, so park it in hidden variables
+                let parked = match *op.right {
+                    Exp::Id(_) | Exp::Num(_) => None,
+                    _ => {
+                        self.i64_depth += 1;
+                        let (lo_var, hi_var) =
+                            i64_halves(&hidden_var(format!("I64ARG{}", self.i64_depth)));
+                        self.generated_code.push_str(&format!(
+                            "sw {}, {}\nsw {}, {}\n",
+                            a_lo, lo_var, a_hi, hi_var
+                        ));
+                        self.var_set.insert(lo_var.clone());
+                        self.var_set.insert(hi_var.clone());
+                        Some((lo_var, hi_var))
+                    }
+                };
+                let (b_lo, b_hi) = self.visit_i64_exp(&op.right);
+                if let Some((lo_var, hi_var)) = parked {
+                    self.i64_depth -= 1;
+                    a_lo = self.get_temp();
+                    a_hi = self.get_temp();
+                    self.generated_code.push_str(&format!(
+                        "move {}, {}\nmove {}, {}\n",
+                        a_lo, lo_var, a_hi, hi_var
+                    ));
+                }
+                let t = self.get_temp();
+                match op.op.as_str() {
+                    "add" => {
+                        // carry out of the low word is set when the sum wraps below an addend
+                        self.generated_code.push_str(&format!(
+                            "addu {a_lo}, {a_lo}, {b_lo}\nsltu {t}, {a_lo}, {b_lo}\naddu {a_hi}, {a_hi}, {b_hi}\naddu {a_hi}, {a_hi}, {t}\n"
+                        ));
+                    }
+                    "sub" => {
+                        self.generated_code.push_str(&format!(
+                            "sltu {t}, {a_lo}, {b_lo}\nsubu {a_lo}, {a_lo}, {b_lo}\nsubu {a_hi}, {a_hi}, {b_hi}\nsubu {a_hi}, {a_hi}, {t}\n"
+                        ));
+                    }
+                    "mul" => {
+                        // low 64 bits of the product: full lo*lo plus the low halves of the cross terms
+                        self.generated_code.push_str(&format!(
+                            "mul {t}, {a_lo}, {b_hi}\nmul {a_hi}, {a_hi}, {b_lo}\naddu {a_hi}, {a_hi}, {t}\nmultu {a_lo}, {b_lo}\nmflo {a_lo}\nmfhi {t}\naddu {a_hi}, {a_hi}, {t}\n"
+                        ));
+                    }
+                    _ => self.errors.push(CompileError::I64Division(op.span.clone())),
+                }
+                (a_lo, a_hi)
+            }
+            Exp::Num(num) if !self.fixed_point && wide_literal(num).is_some() => {
+                let value = wide_literal(num).unwrap_or(0);
+                let lo = self.get_temp();
+                let hi = self.get_temp();
+                self.generated_code.push_str(&format!(
+                    "li {}, {}\nli {}, {}\n",
+                    lo,
+                    value as i32,
+                    hi,
+                    (value >> 32) as i32
+                ));
+                (lo, hi)
+            }
+            _ => {
+                let value = exp.accept(self);
+                let lo = self.operand_register(value);
+                let hi = self.get_temp();
+                self.generated_code
+                    .push_str(&format!("sra {}, {}, 31\n", hi, lo));
+                (lo, hi)
+            }
+        }
+    }
+
+    // Sets a fresh temp to 1 if a < b as signed 64-bit values: the high words
+    // decide unless they are equal, then the low words compare unsigned.
+    fn i64_less_than(&mut self, a: (String, String), b: (String, String)) -> String {
+        let (a_lo, a_hi) = a;
+        let (b_lo, b_hi) = b;
+        let lt = self.get_temp();
+        let eq = self.get_temp();
+        let low = self.get_temp();
+        self.generated_code.push_str(&format!(
+            "slt {lt}, {a_hi}, {b_hi}\nxor {eq}, {a_hi}, {b_hi}\nsltiu {eq}, {eq}, 1\nsltu {low}, {a_lo}, {b_lo}\nand {low}, {low}, {eq}\nor {lt}, {lt}, {low}\n"
+        ));
+        lt
+    }
+
+    // Inputs are evaluated first and parked in hidden variables, so that binding
+    // the operand registers right before the template cannot be disturbed by the
+    // temps an expression uses. Operand registers avoid the declared clobbers. The
+    // template sits between `# asm` and `# endasm` markers: the allocator leaves
+    // those lines alone and keeps variables that are live there out of clobbered
+    // $s registers.
+    fn visit_asm(&mut self, asm: &AsmStm) {
+        let mut clobbers = Vec::new();
+        for operand in &asm.operands {
+            if let AsmOperand::Clobber(reg) = operand {
+                if reg.len() < 2 || !reg.starts_with('$') {
+                    self.errors
+                        .push(CompileError::AsmClobber(reg.clone(), asm.span.clone()));
+                }
+                clobbers.push(reg.clone());
+            }
+        }
+
+        self.asm_depth += 1;
+        let mut inputs = Vec::new();
+        for (i, operand) in asm.operands.iter().enumerate() {
+            if let AsmOperand::In(exp) = operand {
+                if self.is_i64(exp) {
+                    self.errors.push(CompileError::AsmI64(asm.span.clone()));
+                }
+                let mut value = exp.accept(self);
+                if value.starts_with('$') {
+                    let var = hidden_var(format!("ASMARG{}_{}", self.asm_depth, i));
+                    self.generated_code
+                        .push_str(&format!("sw {}, {}\n", value, var));
+                    self.var_set.insert(var.clone());
+                    value = var;
+                }
+                inputs.push(value);
+            }
+        }
+        self.asm_depth -= 1;
+
+        let mut registers = Vec::new();
+        let mut inputs = inputs.into_iter();
+        for operand in &asm.operands {
+            let input = match operand {
+                AsmOperand::In(_) => inputs.next(),
+                AsmOperand::Out(_) => None,
+                AsmOperand::Clobber(_) => continue,
+            };
+            let register = (0..8)
+                .map(|_| self.get_temp())
+                .find(|t| !clobbers.contains(t) && !registers.contains(t));
+            let register = match register {
+                Some(register) => register,
+                None => {
+                    self.errors
+                        .push(CompileError::AsmRegisters(asm.span.clone()));
+                    return;
+                }
+            };
+            if let Some(value) = input {
+                if self.is_numeric(&value) {
+                    self.generated_code
+                        .push_str(&format!("li {}, {}\n", register, value));
+                } else {
+                    self.generated_code
+                        .push_str(&format!("move {}, {}\n", register, value));
+                }
+            }
+            registers.push(register);
+        }
+
+        self.generated_code
+            .push_str(&format!("# asm clobber {}\n", clobbers.join(" ")));
+        for line in template_lines(&asm.template) {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            match substitute_operands(line, &registers) {
+                Ok(line) => {
+                    self.generated_code.push_str(&line);
+                    self.generated_code.push('\n');
+                }
+                Err(operand) => self
+                    .errors
+                    .push(CompileError::AsmOperand(operand, asm.span.clone())),
+            }
+        }
+        self.generated_code.push_str("# endasm\n");
+
+        let mut registers = registers.iter();
+        for operand in &asm.operands {
+            match operand {
+                AsmOperand::Out(id) => {
+                    let register = registers.next().unwrap();
+                    if let Exp::Id(id) = id.as_ref() {
+                        if self.consts.contains_key(&id.id) {
+                            self.errors.push(CompileError::ConstAssignment(
+                                id.id.clone(),
+                                id.span.clone(),
+                            ));
+                            continue;
+                        }
+                    }
+                    if self.is_i64(id) {
+                        self.errors.push(CompileError::AsmI64(asm.span.clone()));
+                    }
+                    let var = id.accept(self);
+                    self.generated_code
+                        .push_str(&format!("sw {}, {}\n", register, var));
+                }
+                AsmOperand::In(_) => {
+                    registers.next();
+                }
+                AsmOperand::Clobber(_) => {}
+            }
+        }
+    }
+
+    // Reports an unknown name, a wrong number of arguments, or an intrinsic that
+    // has no lowering in the current mode.
+    fn check_call(&mut self, call: &CallExp) -> bool {
+        let found = call.args.exps().len();
+        let arity = match intrinsics::arity(&call.name) {
+            Some(arity) => arity,
+            None => {
+                self.errors.push(CompileError::UnknownFunction(
+                    call.name.clone(),
+                    call.span.clone(),
+                ));
+                return false;
+            }
+        };
+        if found != arity {
+            self.errors.push(CompileError::IntrinsicArity(
+                call.name.clone(),
+                arity,
+                found,
+                call.span.clone(),
+            ));
+            return false;
+        }
+        // both would need the Q16.16 scaling undone and redone around the integer loop
+        if self.fixed_point && (call.name == "pow" || call.name == "sqrt") {
+            self.errors.push(CompileError::IntrinsicFixedPoint(
+                call.name.clone(),
+                call.span.clone(),
+            ));
+            return false;
+        }
+        true
+    }
+
+    // With more than one argument, a result left in a temp is parked in a hidden
+    // variable so the temps of the next argument cannot overwrite it. Calls nested
+    // in an argument park theirs under a deeper name.
+    fn visit_call(&mut self, call: &CallExp) -> String {
+        if !self.check_call(call) {
+            return String::from("0");
+        }
+        let exps = call.args.exps();
+
+        self.call_depth += 1;
+        let mut args = Vec::new();
+        for (i, exp) in exps.iter().enumerate() {
+            if matches!(exp, Exp::Str(_)) || self.is_i64(exp) {
+                self.errors.push(CompileError::IntrinsicArgument(
+                    call.name.clone(),
+                    call.span.clone(),
+                ));
+            }
+            let mut value = exp.accept(self);
+            if value.starts_with('$') && exps.len() > 1 {
+                let var = hidden_var(format!("CALLARG{}_{}", self.call_depth, i));
+                self.generated_code
+                    .push_str(&format!("sw {}, {}\n", value, var));
+                self.var_set.insert(var.clone());
+                value = var;
+            }
+            args.push(value);
+        }
+        self.call_depth -= 1;
+
+        let constants: Option<Vec<i32>> = args.iter().map(|arg| arg.parse().ok()).collect();
+        if let Some(value) = constants.and_then(|c| intrinsics::fold(&call.name, &c)) {
+            return value.to_string();
+        }
+
+        // every argument ends up in a temp of its own, free to be overwritten
+        let args: Vec<String> = args
+            .into_iter()
+            .map(|arg| {
+                if arg.starts_with('$') {
+                    arg
+                } else {
+                    let temp = self.get_temp();
+                    let instr = if self.is_numeric(&arg) { "li" } else { "move" };
+                    self.generated_code
+                        .push_str(&format!("{} {}, {}\n", instr, temp, arg));
+                    temp
+                }
+            })
+            .collect();
+        let dest = self.get_temp();
+        match call.name.as_str() {
+            "abs" => {
+                // the sign mask is all ones for a negative x: (x ^ -1) - -1 == -x
+                let x = &args[0];
+                let sign = self.get_temp();
+                self.generated_code.push_str(&format!(
+                    "sra {sign}, {x}, 31\nxor {dest}, {x}, {sign}\nsubu {dest}, {dest}, {sign}\n"
+                ));
+            }
+            "min" | "max" => {
+                // mask is all ones when a < b; other ^ ((a ^ b) & mask) is then a or b
+                let (a, b) = (&args[0], &args[1]);
+                let mask = self.get_temp();
+                let other = if call.name == "min" { b } else { a };
+                self.generated_code.push_str(&format!(
+                    "slt {mask}, {a}, {b}\nsubu {mask}, $zero, {mask}\nxor {dest}, {a}, {b}\nand {dest}, {dest}, {mask}\nxor {dest}, {other}, {dest}\n"
+                ));
+            }
+            "pow" => {
+                let (base, exp) = (&args[0], &args[1]);
+                let bit = self.get_temp();
+                let loop_label = self.gen_label();
+                let skip_label = self.gen_label();
+                let end_label = self.gen_label();
+                self.generated_code.push_str(&format!(
+                    "li {dest}, 1\nbge {exp}, $zero, {loop_label}\nli {dest}, 0\n{loop_label}:\nble {exp}, $zero, {end_label}\nandi {bit}, {exp}, 1\nbeq {bit}, $zero, {skip_label}\nmul {dest}, {dest}, {base}\n{skip_label}:\nmul {base}, {base}, {base}\nsra {exp}, {exp}, 1\nj {loop_label}\n{end_label}:\n"
+                ));
+            }
+            _ => {
+                // Newton steps from (x + 1) / 2 down to floor(sqrt(x)); the sum
+                // is computed unsigned so x = 2^31 - 1 cannot overflow
+                let x = &args[0];
+                let next = self.get_temp();
+                let quot = self.get_temp();
+                let loop_label = self.gen_label();
+                let end_label = self.gen_label();
+                self.generated_code.push_str(&format!(
+                    "li {dest}, 0\nble {x}, $zero, {end_label}\nmove {dest}, {x}\naddiu {next}, {x}, 1\nsrl {next}, {next}, 1\n{loop_label}:\nbge {next}, {dest}, {end_label}\nmove {dest}, {next}\ndiv {quot}, {x}, {next}\naddu {next}, {next}, {quot}\nsrl {next}, {next}, 1\nj {loop_label}\n{end_label}:\n"
+                ));
+            }
+        }
+        dest
+    }
+
+    // Declares `const id := exp`. The name must not have been used yet, since
+    // earlier uses were compiled as a variable.
+    fn declare_const(&mut self, const_stm: &ConstStm) {
+        if self.consts.contains_key(&const_stm.id) {
+            self.errors.push(CompileError::ConstRedeclaration(
+                const_stm.id.clone(),
+                const_stm.span.clone(),
+            ));
+            return;
+        }
+        let (lo, _) = i64_halves(&const_stm.id);
+        if self.var_set.contains(&const_stm.id) || self.var_set.contains(&lo) {
+            self.errors.push(CompileError::ConstDeclaration(
+                const_stm.id.clone(),
+                const_stm.span.clone(),
+            ));
+            return;
+        }
+        match self.const_value(&const_stm.exp) {
+            Ok(value) => {
+                self.consts.insert(const_stm.id.clone(), value);
+            }
+            Err(reason) => {
+                self.errors.push(CompileError::ConstValue(
+                    const_stm.id.clone(),
+                    reason,
+                    const_stm.span.clone(),
+                ));
+                // keeps later uses from also being reported
+                self.consts.insert(const_stm.id.clone(), 0);
+            }
+        }
+    }
+
+    // Evaluates a constant expression exactly as the emitted code would, in the
+    // current arithmetic and number modes.
+    fn const_value(&mut self, exp: &Exp) -> Result<i32, &'static str> {
+        match exp {
+            // a literal that cannot be used here has already been reported
+            Exp::Num(num) => Ok(self.num_value(num).parse().unwrap_or(0)),
+            Exp::Id(id) => match self.consts.get(&id.id) {
+                Some(value) => Ok(*value),
+                None => Err("is not a compile-time constant"),
+            },
+            Exp::Op(op) => {
+                let left = self.const_value(&op.left)?;
+                let right = self.const_value(&op.right)?;
+                self.const_op(&op.op, left, right)
+            }
+            Exp::Call(call) => {
+                let mut args = Vec::new();
+                for arg in call.args.exps() {
+                    args.push(self.const_value(arg)?);
+                }
+                if !self.check_call(call) {
+                    return Ok(0);
+                }
+                Ok(intrinsics::fold(&call.name, &args).unwrap_or(0))
+            }
+            Exp::Eseq(_) | Exp::Comp(_) | Exp::Str(_) => Err("is not a compile-time constant"),
+        }
+    }
+
+    fn const_op(&self, op: &str, a: i32, b: i32) -> Result<i32, &'static str> {
+        const OVERFLOW: &str = "overflows 32 bits";
+        let checked = self.checked_arith;
+        match op {
+            "add" if checked => a.checked_add(b).ok_or(OVERFLOW),
+            "add" => Ok(a.wrapping_add(b)),
+            "sub" if checked => a.checked_sub(b).ok_or(OVERFLOW),
+            "sub" => Ok(a.wrapping_sub(b)),
+            "mul" if self.fixed_point => {
+                // the middle word of the Q32.32 product, as fixed_mul takes it
+                let product = (a as i64 * b as i64) >> 16;
+                if checked {
+                    i32::try_from(product).map_err(|_| OVERFLOW)
+                } else {
+                    Ok(product as i32)
+                }
+            }
+            "mul" if checked => a.checked_mul(b).ok_or(OVERFLOW),
+            "mul" => Ok(a.wrapping_mul(b)),
+            _ if b == 0 => Err("divides by zero"),
+            _ if self.fixed_point => {
+                // FIXDIV divides the magnitudes and keeps the low 32 bits of the quotient
+                let quotient = ((a.unsigned_abs() as u64) << 16) / b.unsigned_abs() as u64;
+                let quotient = quotient as u32 as i32;
+                Ok(if (a ^ b) < 0 {
+                    quotient.wrapping_neg()
+                } else {
+                    quotient
+                })
+            }
+            _ => Ok(a.wrapping_div(b)),
+        }
+    }
+
+    fn require_routine(&mut self, label: &str, code: &str) {
+        if !self.runtime.contains(&format!("{}:\n", label)) {
+            self.runtime.push_str(code);
+        }
+    }
+
+    fn require_overflow_handler(&mut self) {
+        if self.runtime.contains("OVERFLOW:") {
+            return;
+        }
+        let message = self.string_label("arithmetic overflow at ");
+        self.runtime.push_str("OVERFLOW:\n");
+        self.print_string_to_runtime(&message);
+        self.runtime.push_str("li $v0, 4\nmove $a0, $v1\nsyscall\n");
+        self.print_string_to_runtime("newline");
+        self.runtime.push_str("li $v0, 17\nli $a0, 1\nsyscall\n");
+        // trapping add/sub land here; the only exception checked code can raise is overflow
+        self.runtime.push_str(".ktext 0x80000180\n");
+        self.runtime
+            .push_str("la $k0, OVERFLOW\nmtc0 $k0, $14\neret\n");
+        self.runtime.push_str(".text\n");
+    }
+
+    fn print_string_to_runtime(&mut self, label: &str) {
+        self.runtime.push_str("li $v0, 4\nla $a0, ");
+        self.runtime.push_str(label);
+        self.runtime.push_str("\nsyscall\n");
+    }
+
+    // Helper routines go after an explicit exit so the main program can't fall into them.
+    pub fn runtime_code(&self) -> String {
+        if self.runtime.is_empty() {
+            return String::new();
+        }
+        let mut code = String::from("li $v0, 10\nsyscall\n");
+        code.push_str(&self.runtime);
+        code
+    }
+
+    pub fn string_data(&self) -> String {
+        let mut data = String::new();
+        for (i, value) in self.strings.iter().enumerate() {
+            data.push_str(&format!("STRLABEL{}: .asciiz \"{}\"\n", i, value));
+        }
+        data
+    }
 }
 
 impl Visitor for CodeGenerator {
     fn visit_stm(&mut self, stm: &Stm) {
         match stm {
             Stm::Assign(assign) => {
+                if let Exp::Id(id) = assign.id.as_ref() {
+                    if self.consts.contains_key(&id.id) {
+                        self.errors.push(CompileError::ConstAssignment(
+                            id.id.clone(),
+                            id.span.clone(),
+                        ));
+                        return;
+                    }
+                    if self.i64_vars.contains(&id.id) {
+                        let (lo, hi) = self.visit_i64_exp(&assign.exp);
+                        let (lo_var, hi_var) = i64_halves(&id.id);
+                        self.generated_code
+                            .push_str(&format!("sw {}, {}\nsw {}, {}\n", lo, lo_var, hi, hi_var));
+                        self.var_set.insert(lo_var);
+                        self.var_set.insert(hi_var);
+                        return;
+                    }
+                }
+                if self.is_i64(&assign.exp) {
+                    // storing a 64-bit value into a 32-bit variable keeps the low word
+                    let (lo, _) = self.visit_i64_exp(&assign.exp);
+                    let v1 = assign.id.accept(self);
+                    self.generated_code
+                        .push_str(&format!("sw {}, {}\n", lo, v1));
+                    return;
+                }
+
                 let v1 = assign.id.accept(self);
                 let v2 = assign.exp.accept(self);
 
@@ -189,43 +1431,77 @@ impl Visitor for CodeGenerator {
                 compound.stm2.accept(self);
             }
             Stm::Print(print) => {
-                let arr = print.exps.accept(self);
-                for x in arr {
-                    if let Some((_, ch)) = x.char_indices().next() {
-                        if self.is_numeric(&ch.to_string()) {
-                            let tmp = self.get_temp();
-                            self.generated_code.push_str("li ");
-                            self.generated_code.push_str(&tmp);
-                            self.generated_code.push_str(", ");
-                            self.generated_code.push_str(&x);
-                            self.generated_code.push_str("\n");
-                            self.load_print_instr();
-                            self.generated_code.push_str("move $a0, ");
-                            self.generated_code.push_str(&tmp);
-                            self.generated_code.push_str("\n");
-                        } else if ch != '$' {
-                            // let tmp = self.get_var();
-                            // self.generated_code.push_str("lw ");
-                            // self.generated_code.push_str(&tmp);
-                            // self.generated_code.push_str(", ");
-                            // self.generated_code.push_str(&x);
-                            // self.generated_code.push_str("\n");
-
-                            self.load_print_instr();
-                            self.generated_code.push_str("move $a0, ");
-                            // self.generated_code.push_str(&tmp);
-                            self.generated_code.push_str(&x);
-                            self.generated_code.push_str("\n");
-                        } else {
-                            self.load_print_instr();
-                            self.generated_code.push_str("move $a0, ");
-                            self.generated_code.push_str(&x);
-                            self.generated_code.push_str("\n");
+                // every argument is evaluated before anything is printed
+                let exps = print.exps.exps();
+                self.print_depth += 1;
+                let mut arr = Vec::new();
+                let mut i64_his = Vec::new();
+                for (i, exp) in exps.iter().enumerate() {
+                    if self.is_i64(exp) && !print.chars {
+                        // a pair would tie up two temps until printing, so park it in hidden variables
+                        let (lo, hi) = self.visit_i64_exp(exp);
+                        let (lo_var, hi_var) =
+                            i64_halves(&hidden_var(format!("PRINTARG{}_{}", self.print_depth, i)));
+                        self.generated_code
+                            .push_str(&format!("sw {}, {}\nsw {}, {}\n", lo, lo_var, hi, hi_var));
+                        self.var_set.insert(lo_var.clone());
+                        self.var_set.insert(hi_var.clone());
+                        arr.push(lo_var);
+                        i64_his.push(Some(hi_var));
+                    } else {
+                        let mut value = exp.accept(self);
+                        if value.starts_with('$') && exps.len() > 1 {
+                            // later arguments can cycle through the temps and overwrite this one
+                            let var = hidden_var(format!("PRINTARG{}_{}", self.print_depth, i));
+                            self.generated_code
+                                .push_str(&format!("sw {}, {}\n", value, var));
+                            self.var_set.insert(var.clone());
+                            value = var;
                         }
-                        self.syscall();
-                        self.newline_instr();
+                        arr.push(value);
+                        i64_his.push(None);
                     }
                 }
+                self.print_depth -= 1;
+                for (i, x) in arr.iter().enumerate() {
+                    if i > 0 && !print.chars {
+                        self.separator_instr();
+                    }
+                    if let Exp::Str(_) = exps[i] {
+                        self.print_string_instr(x);
+                        continue;
+                    }
+                    if let Some(hi) = &i64_his[i] {
+                        self.require_routine("PRINTI64", runtime::PRINT_I64);
+                        self.generated_code.push_str(&format!(
+                            "move $a0, {}\nmove $a1, {}\njal PRINTI64\n",
+                            x, hi
+                        ));
+                        continue;
+                    }
+                    let value = if self.is_numeric(x) {
+                        self.operand_register(x.clone())
+                    } else {
+                        x.clone()
+                    };
+                    if self.fixed_point && !print.chars {
+                        self.require_routine("PRINTFIX", runtime::PRINT_FIX);
+                        self.generated_code
+                            .push_str(&format!("move $a0, {}\njal PRINTFIX\n", value));
+                        continue;
+                    }
+                    self.load_print_instr(print.chars);
+                    self.generated_code
+                        .push_str(&format!("move $a0, {}\n", value));
+                    if self.fixed_point {
+                        // a character is printed from the integer part
+                        self.generated_code.push_str("sra $a0, $a0, 16\n");
+                    }
+                    self.syscall();
+                }
+                if print.newline {
+                    self.newline_instr();
+                }
             }
             Stm::For(for_stm) => {
                 for_stm.iter.accept(self);
@@ -250,16 +1526,34 @@ impl Visitor for CodeGenerator {
                 self.generated_code.push_str(&loop_exit_label);
                 self.generated_code.push_str(":\n");
             }
+            Stm::Asm(asm) => self.visit_asm(asm),
+            Stm::Const(const_stm) => self.declare_const(const_stm),
+            // resolved by the module loader before code generation
+            Stm::Import(_) | Stm::Export(_) => {}
+            // the loader stops on syntax errors, but a recovered tree must never
+            // compile into a program with a statement missing
+            Stm::Error(error) => self.errors.push(CompileError::Unparsed(error.span.clone())),
         }
     }
 
     fn visit_exp(&mut self, exp: &Exp) -> String {
         match exp {
             Exp::Id(id) => {
+                if let Some(value) = self.consts.get(&id.id) {
+                    return value.to_string();
+                }
+                if self.i64_vars.contains(&id.id) {
+                    // read as 32 bits, an i64 variable is its low word
+                    let (lo_var, hi_var) = i64_halves(&id.id);
+                    self.var_set.insert(lo_var.clone());
+                    self.var_set.insert(hi_var);
+                    return lo_var;
+                }
                 self.var_set.insert(id.id.clone());
                 id.id.clone()
             }
-            Exp::Num(num) => num.num.clone(),
+            Exp::Num(num) => self.num_value(num),
+            Exp::Str(str_exp) => self.string_label(&str_exp.value),
             Exp::Op(op) => {
                 let code1 = op.left.accept(self);
                 let code2 = op.right.accept(self);
@@ -303,7 +1597,24 @@ impl Visitor for CodeGenerator {
                 // self.generated_code.push_str(&code2);
                 // self.generated_code.push_str("\n");
 
-                self.generated_code.push_str(&op.op);
+                if self.fixed_point && op.op == "mul" {
+                    self.fixed_mul(&temp_var3, temp_var1, temp_var2, &op.span);
+                    return temp_var3;
+                }
+                if self.fixed_point && op.op == "div" {
+                    self.fixed_div(&temp_var3, temp_var1, temp_var2);
+                    return temp_var3;
+                }
+                if self.checked_arith && op.op == "mul" {
+                    self.checked_mul(&temp_var3, temp_var1, temp_var2, &op.span);
+                    return temp_var3;
+                }
+                if self.checked_arith {
+                    self.overflow_location(&op.span);
+                }
+
+                let instr = self.arith_instr(&op.op);
+                self.generated_code.push_str(instr);
                 self.generated_code.push_str(" ");
                 self.generated_code.push_str(&temp_var3);
                 self.generated_code.push_str(", ");
@@ -318,7 +1629,20 @@ impl Visitor for CodeGenerator {
                 eseq.stm.accept(self);
                 eseq.exp.accept(self)
             }
+            Exp::Call(call) => self.visit_call(call),
             Exp::Comp(comp) => {
+                if self.is_i64(&comp.left) || self.is_i64(&comp.right) {
+                    let left = self.visit_i64_exp(&comp.left);
+                    let right = self.visit_i64_exp(&comp.right);
+                    // the loop exits when the condition is false; `>` is `<` with the operands swapped
+                    let holds = if comp.op == 0 {
+                        self.i64_less_than(left, right)
+                    } else {
+                        self.i64_less_than(right, left)
+                    };
+                    return format!("beq {}, $zero, ", holds);
+                }
+
                 let mut b_instr = String::new();
                 let mut left = comp.left.accept(self);
                 let right = comp.right.accept(self);
@@ -347,21 +1671,257 @@ impl Visitor for CodeGenerator {
             }
         }
     }
+}
 
-    fn visit_exp_list(&mut self, exp_list: &ExpList) -> Vec<String> {
-        match exp_list {
-            ExpList::Pair(pair) => {
-                let v1 = pair.head.accept(self);
-                let mut v2 = pair.tail.accept(self);
-                let mut new_arr = vec![v1];
-                new_arr.append(&mut v2);
-                new_arr
-            }
-            ExpList::Last(last) => {
-                let v1 = last.head.accept(self);
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use super::{collect_i64_vars, CodeGenerator};
+    use crate::preprocess::SourceMap;
+    use crate::test_support::{parse_str_descent, TempDir};
+
+    // A generator that has run over `source`.
+    fn code_gen(source: &str) -> CodeGenerator {
+        let prog = parse_str_descent(source).clean(source);
+        let mut code_gen = generator();
+        collect_i64_vars(&prog, &mut code_gen.i64_vars);
+        prog.accept(&mut code_gen);
+        code_gen
+    }
 
-                vec![v1]
+    fn generator() -> CodeGenerator {
+        CodeGenerator {
+            generated_code: String::new(),
+            temp_var_num: 0,
+            var_num: 0,
+            label_num: 0,
+            var_set: HashSet::new(),
+            strings: Vec::new(),
+            print_sep: String::from(" "),
+            checked_arith: false,
+            fixed_point: false,
+            runtime: String::new(),
+            i64_vars: HashSet::new(),
+            errors: Vec::new(),
+            source_map: SourceMap::default(),
+            call_depth: 0,
+            i64_depth: 0,
+            print_depth: 0,
+            asm_depth: 0,
+            consts: HashMap::new(),
+        }
+    }
+
+    // What straight-line generated code leaves behind: every register and
+    // variable, held as a word by name, and what it printed.
+    struct Run {
+        words: HashMap<String, u32>,
+        output: String,
+    }
+
+    // Runs the code a generator emitted. Print syscalls and the i64 print
+    // routine write to `output`.
+    fn run(code_gen: &CodeGenerator) -> Run {
+        let mut words: HashMap<String, u32> = HashMap::new();
+        // the label each register was last loaded with by `la`
+        let mut labels: HashMap<String, String> = HashMap::new();
+        let mut output = String::new();
+        for line in code_gen.generated_code.lines() {
+            if line.starts_with('#') {
+                continue;
             }
+            let (op, operands) = line.split_once(' ').unwrap_or((line, ""));
+            let operands: Vec<&str> = operands.split(", ").collect();
+            let word = |words: &HashMap<String, u32>, operand: &str| match operand.parse::<i32>() {
+                Ok(num) => num as u32,
+                Err(_) => words[operand],
+            };
+            let (dest, value) = match (op, operands.as_slice()) {
+                ("li" | "move", [d, a]) => (d.to_string(), word(&words, a)),
+                ("sw", [a, d]) => (d.to_string(), word(&words, a)),
+                ("sra", [d, a, b]) => (
+                    d.to_string(),
+                    ((word(&words, a) as i32) >> word(&words, b)) as u32,
+                ),
+                ("addu" | "add", [d, a, b]) => {
+                    (d.to_string(), word(&words, a).wrapping_add(word(&words, b)))
+                }
+                ("subu" | "sub", [d, a, b]) => {
+                    (d.to_string(), word(&words, a).wrapping_sub(word(&words, b)))
+                }
+                ("mul", [d, a, b]) => {
+                    (d.to_string(), word(&words, a).wrapping_mul(word(&words, b)))
+                }
+                ("sltu", [d, a, b]) => (d.to_string(), (word(&words, a) < word(&words, b)) as u32),
+                ("multu", [a, b]) => {
+                    let product = word(&words, a) as u64 * word(&words, b) as u64;
+                    words.insert(String::from("hi"), (product >> 32) as u32);
+                    (String::from("lo"), product as u32)
+                }
+                ("mflo", [d]) => (d.to_string(), words["lo"]),
+                ("mfhi", [d]) => (d.to_string(), words["hi"]),
+                ("la", [d, label]) => {
+                    labels.insert(d.to_string(), label.to_string());
+                    continue;
+                }
+                ("syscall", _) => {
+                    match words["$v0"] {
+                        1 => output.push_str(&(words["$a0"] as i32).to_string()),
+                        11 => output.push(char::from(words["$a0"] as u8)),
+                        4 => match labels["$a0"].strip_prefix("STRLABEL") {
+                            Some(i) => {
+                                output.push_str(&code_gen.strings[i.parse::<usize>().unwrap()])
+                            }
+                            None => output.push('\n'),
+                        },
+                        code => panic!("can't run syscall {}", code),
+                    }
+                    continue;
+                }
+                ("jal", ["PRINTI64"]) => {
+                    let value = (words["$a1"] as u64) << 32 | words["$a0"] as u64;
+                    output.push_str(&(value as i64).to_string());
+                    continue;
+                }
+                _ => panic!("can't run {}", line),
+            };
+            words.insert(dest, value);
+        }
+        Run { words, output }
+    }
+
+    // The value of the i64 variable `var` after running `source`.
+    fn run_i64(source: &str, var: &str) -> i64 {
+        let code_gen = code_gen(source);
+        assert!(code_gen.errors.is_empty(), "{}", source);
+        let words = run(&code_gen).words;
+        let (lo, hi) = super::i64_halves(var);
+        ((words[&hi] as u64) << 32 | words[&lo] as u64) as i64
+    }
+
+    // What `source` prints.
+    fn output(source: &str) -> String {
+        let code_gen = code_gen(source);
+        assert!(code_gen.errors.is_empty(), "{}", source);
+        run(&code_gen).output
+    }
+
+    #[test]
+    fn i64_left_operand_survives_a_long_right_operand() {
+        // the right operand runs through every temp before the multiply
+        assert_eq!(
+            run_i64(
+                "i64 x := 5;\ny := 1;\nx := x * y + y + y + y + y + y + y",
+                "x"
+            ),
+            35
+        );
+    }
+
+    #[test]
+    fn i64_left_operands_nest() {
+        // x + (z * (z + (z * (z + (z * (z + z))))))
+        assert_eq!(
+            run_i64(
+                "i64 x := 5;\ni64 z := 2;\nx := x + z * z + z * z + z * z + z",
+                "x"
+            ),
+            49
+        );
+        // x * (x - 1)
+        assert_eq!(
+            run_i64("i64 x := 3000000;\nx := x * x - 1", "x"),
+            3_000_000 * (3_000_000 - 1)
+        );
+    }
+
+    #[test]
+    fn wide_literals_in_i64_expressions() {
+        assert_eq!(run_i64("i64 big := 3000000000", "big"), 3_000_000_000);
+        assert_eq!(
+            run_i64("i64 x := 5;\nx := 3000000000 + x", "x"),
+            3_000_000_005
+        );
+        assert_eq!(
+            run_i64("i64 x := 1;\nx := x - 9223372036854775807", "x"),
+            1 - i64::MAX
+        );
+    }
+
+    #[test]
+    fn nested_prints_park_their_arguments_apart() {
+        // the inner print's 3+4 must not land where the outer print parked 1+2
+        assert_eq!(output("print(1+2, (print(3+4, 5), 6))"), "7 5\n3 6\n");
+    }
+
+    #[test]
+    fn nested_i64_prints_park_their_arguments_apart() {
+        assert_eq!(
+            output("i64 x := 3000000000;\nprint(x + 1, (print(x + 2, 5), 6))"),
+            "3000000002 5\n3000000001 6\n"
+        );
+    }
+
+    #[test]
+    fn nested_asm_parks_its_inputs_apart() {
+        // the inner asm's 3+4 must not land where the outer asm parked 1+2
+        let source = "asm(\"addu %0, %1, %2\", out(r), in(1+2), \
+                      in((asm(\"addu %0, %1, %1\", out(s), in(3+4)), 10)));\n\
+                      print(r, s)";
+        assert_eq!(output(source), "13 14\n");
+    }
+
+    #[test]
+    fn hidden_variables_stay_apart_from_program_variables() {
+        // each program variable is named like the place 1+2 gets parked
+        assert_eq!(
+            output("PRINTARG1_0 := 5;\nprint(1+2, PRINTARG1_0)"),
+            "3 5\n"
+        );
+        let source = "ASMARG1_1 := 5;\n\
+                      asm(\"addu %0, %1, %2\", out(r), in(1+2), in(ASMARG1_1));\n\
+                      print(r)";
+        assert_eq!(output(source), "8\n");
+    }
+
+    #[test]
+    fn i64_halves_stay_apart_from_module_variables() {
+        let dir = TempDir::new("ast");
+        dir.write("a.slp", "export lo;\nlo := 7");
+        dir.write("main.slp", "import a;\ni64 a := 5;\nprint(a, a.lo)");
+        let modules = dir.load("main.slp");
+
+        // a suffix would name both the low half of `a` and the `lo` of module `a` `a.lo`
+        let mut code_gen = generator();
+        for module in &modules {
+            collect_i64_vars(&module.prog, &mut code_gen.i64_vars);
+        }
+        for module in &modules {
+            module.prog.accept(&mut code_gen);
+        }
+        assert!(code_gen.errors.is_empty());
+        assert_eq!(run(&code_gen).output, "5 7\n");
+    }
+
+    #[test]
+    fn wide_literals_are_rejected_in_32_bit_code() {
+        for source in [
+            "y := 3000000000",
+            "const C := 5000000000",
+            "print(1, 3000000000)",
+        ] {
+            let errors: Vec<String> = code_gen(source)
+                .errors
+                .iter()
+                .map(|err| err.to_string())
+                .collect();
+            assert_eq!(errors.len(), 1, "{}", source);
+            assert!(
+                errors[0].contains("only an i64 expression can use it"),
+                "{}",
+                source
+            );
         }
     }
 }
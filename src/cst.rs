@@ -0,0 +1,276 @@
+use std::fmt;
+
+use crate::ast::NodeIds;
+use crate::errors::ParseError;
+use crate::grammar::ERROR;
+use crate::ir_code_gen::{gen_line, SemanticValue, StackItem};
+use crate::lex::{Span, Token};
+
+// The concrete syntax tree the SLR parser builds: every token it took, as
+// written, grouped by the productions it reduced them with. The text between two
+// tokens is trivia: up to and including the end of a token's line it trails that
+// token, and the rest leads the next one. Anything the lexer rejected or error
+// recovery skipped is kept as trivia too, so printing a tree gives back the
+// parser's input byte for byte. That input is the preprocessed text, so the round
+// trip is exact only for it: a #define line comes back blank, and an #include line
+// or a macro as the text it expanded to.
+pub struct SyntaxTree {
+    pub prog: SyntaxNode,
+    // EndOfFile, whose leading trivia is whatever follows the last token
+    pub end: SyntaxToken,
+}
+
+pub struct SyntaxNode {
+    // the production's left-hand side
+    pub kind: String,
+    pub prod: usize,
+    pub children: Vec<SyntaxElement>,
+}
+
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+pub struct SyntaxToken {
+    // Token::name(), or `error` where the parser recovered from a syntax error.
+    // An `error` token has no text; what it replaced is trivia of the next token.
+    pub kind: String,
+    // Token::val(), which is what gen_line reads
+    pub value: String,
+    pub span: Span,
+    pub leading: String,
+    pub text: String,
+    pub trailing: String,
+}
+
+impl SyntaxToken {
+    // Trivia and text are filled in by SyntaxTree::attach_text once the whole
+    // file is parsed.
+    pub fn new(tok: &Token) -> SyntaxToken {
+        SyntaxToken {
+            kind: tok.name().to_string(),
+            value: tok.val(),
+            span: tok.span().clone(),
+            leading: String::new(),
+            text: String::new(),
+            trailing: String::new(),
+        }
+    }
+
+    // Shifted in place of the statement a syntax error was found in; `span` is
+    // the token the error was found at.
+    pub fn error(span: Span) -> SyntaxToken {
+        SyntaxToken {
+            kind: String::from(ERROR),
+            value: String::from(ERROR),
+            span,
+            leading: String::new(),
+            text: String::new(),
+            trailing: String::new(),
+        }
+    }
+}
+
+impl SyntaxElement {
+    pub fn span(&self) -> Span {
+        match self {
+            SyntaxElement::Node(node) => node.span(),
+            SyntaxElement::Token(tok) => tok.span.clone(),
+        }
+    }
+}
+
+impl SyntaxNode {
+    // From the start of the first token to the end of the last.
+    pub fn span(&self) -> Span {
+        match (self.children.first(), self.children.last()) {
+            (Some(first), Some(last)) => first.span().to(&last.span()),
+            _ => Span::default(),
+        }
+    }
+
+    fn tokens_mut<'a>(&'a mut self, tokens: &mut Vec<&'a mut SyntaxToken>) {
+        for child in &mut self.children {
+            match child {
+                SyntaxElement::Node(node) => node.tokens_mut(tokens),
+                SyntaxElement::Token(tok) => tokens.push(tok),
+            }
+        }
+    }
+
+    fn dump(&self, depth: usize, out: &mut String) {
+        out.push_str(&format!(
+            "{}{} (production {})\n",
+            "  ".repeat(depth),
+            self.kind,
+            self.prod
+        ));
+        for child in &self.children {
+            match child {
+                SyntaxElement::Node(node) => node.dump(depth + 1, out),
+                SyntaxElement::Token(tok) => tok.dump(depth + 1, out),
+            }
+        }
+    }
+}
+
+impl SyntaxToken {
+    fn dump(&self, depth: usize, out: &mut String) {
+        out.push_str(&format!(
+            "{}{} {:?}",
+            "  ".repeat(depth),
+            self.kind,
+            self.text
+        ));
+        if !self.leading.is_empty() {
+            out.push_str(&format!(" leading {:?}", self.leading));
+        }
+        if !self.trailing.is_empty() {
+            out.push_str(&format!(" trailing {:?}", self.trailing));
+        }
+        out.push('\n');
+    }
+}
+
+impl SyntaxTree {
+    // Fills in every token's text and trivia from `source`, the text the lexer
+    // read, using the tokens' spans.
+    pub fn attach_text(&mut self, source: &str) {
+        let mut tokens = Vec::new();
+        self.prog.tokens_mut(&mut tokens);
+        tokens.push(&mut self.end);
+        let mut tokens: Vec<&mut SyntaxToken> =
+            tokens.into_iter().filter(|tok| tok.kind != ERROR).collect();
+
+        let mut pos = 0;
+        for tok in tokens.iter_mut() {
+            let start = tok.span.start.clamp(pos, source.len());
+            let end = tok.span.end.clamp(start, source.len());
+            tok.leading = source[pos..start].to_string();
+            tok.text = source[start..end].to_string();
+            pos = end;
+        }
+        for i in 1..tokens.len() {
+            let (before, after) = tokens.split_at_mut(i);
+            let (prev, tok) = (&mut before[i - 1], &mut after[0]);
+            let line_end = tok.leading.find('\n').map_or(tok.leading.len(), |n| n + 1);
+            prev.trailing = tok.leading.drain(..line_end).collect();
+        }
+    }
+
+    // One node or token per line, indented by depth, for --print-cst.
+    pub fn dump(&self) -> String {
+        let mut out = String::new();
+        self.prog.dump(0, &mut out);
+        self.end.dump(0, &mut out);
+        out
+    }
+}
+
+impl fmt::Display for SyntaxTree {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.prog, self.end)
+    }
+}
+
+impl fmt::Display for SyntaxNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for child in &self.children {
+            match child {
+                SyntaxElement::Node(node) => write!(f, "{}", node)?,
+                SyntaxElement::Token(tok) => write!(f, "{}", tok)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for SyntaxToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}{}", self.leading, self.text, self.trailing)
+    }
+}
+
+// Builds the AST value of a node whose children have been lowered already, with
+// gen_line, which reads the node's production and first symbol. The parser
+// lowers each node as it completes it, so the AST is the CST lowered bottom-up,
+// and every AST node spans the tokens of the CST node it came from.
+pub fn lower_node(
+    node: &SyntaxNode,
+    values: &mut Vec<SemanticValue>,
+    ids: &mut NodeIds,
+) -> Result<(), ParseError> {
+    let first = match node.children.first() {
+        Some(SyntaxElement::Token(tok)) => StackItem::Value(tok.value.clone(), tok.span.clone()),
+        Some(SyntaxElement::Node(child)) => {
+            StackItem::Nonterminal(child.kind.clone(), child.span())
+        }
+        None => {
+            return Err(ParseError::InternalGrammar(
+                node.prod,
+                String::from("the production has no symbols"),
+                Span::default(),
+            ))
+        }
+    };
+    gen_line(node.prod, values, first, &node.span(), ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SyntaxTree;
+    use crate::preprocess::preprocess;
+    use crate::test_support::{parse_str_slr, table, TempDir};
+
+    fn tree(source: &str) -> SyntaxTree {
+        match parse_str_slr(&table(), source).prog {
+            Some((tree, _)) => tree,
+            None => panic!("no tree for {:?}", source),
+        }
+    }
+
+    fn assert_round_trip(source: &str) {
+        assert_eq!(tree(source).to_string(), source);
+    }
+
+    #[test]
+    fn round_trip_keeps_layout() {
+        assert_round_trip("x := 1;\nprint(x)");
+        assert_round_trip("x := 1;\r\nprint(x)\r\n");
+        assert_round_trip("\tx := 1;\n\tfor (i := 0; i < 2; i := i + 1) {\n\t\tprint(i)\n\t}");
+        assert_round_trip("x := 1;   \nprint(x)  \t\n\n  ");
+        assert_round_trip("\n\n  x := 1\n\n");
+    }
+
+    #[test]
+    fn round_trip_keeps_what_the_lexer_rejected() {
+        assert_round_trip("x := 1 @;\nprint(x)");
+        assert_round_trip("  \n# not a directive\nx := 1 // no comments\n");
+        assert_round_trip("x := 99999999999999999999;\nprint(\"a\\qb\")");
+    }
+
+    #[test]
+    fn round_trip_keeps_what_error_recovery_skipped() {
+        assert_round_trip("a := ;\nb := 1");
+        assert_round_trip("a := 1 +;\nb := ;\nprint(a b)");
+        assert_round_trip("for (i := 0; i 3; i := i + 1) { print(i) };\nx := 1");
+    }
+
+    #[test]
+    fn round_trip_is_of_the_preprocessed_text() {
+        let dir = TempDir::new("cst");
+        let original = "#define N 4\n#include \"inc.slp\"\nx := N;\r\nprint(x, y)";
+        dir.write("main.slp", original);
+        dir.write("inc.slp", "y := 2;\n");
+        let (text, _) = match preprocess(&dir.path("main.slp")) {
+            Ok(preprocessed) => preprocessed,
+            Err(err) => panic!("{}", err),
+        };
+
+        let printed = tree(&text).to_string();
+        assert_eq!(printed, text);
+        assert_ne!(printed, original);
+        assert!(printed.starts_with("\ny := 2;\n"), "{:?}", printed);
+    }
+}
@@ -0,0 +1,590 @@
+use crate::ast::{
+    AsmOperand, AsmStm, AssignStm, CallExp, CompExp, CompoundStm, ConstStm, ErrorStm, EseqExp, Exp,
+    ExpList, ExportStm, ForStm, IdExp, ImportStm, LastExpList, NodeIds, NumExp, OpExp, PairExpList,
+    PrintStm, Stm, StrExp,
+};
+use crate::errors::{ParseError, TokenError};
+use crate::lex::{describe_token, Lexer, Span, Token};
+use crate::parser::peek_token;
+
+const TRAILING_SEMICOLON: &str =
+    "`;` goes between statements, so the last one before a `}` or the end of the file has none";
+const ESEQ_OPERAND: &str = "only a name, number, character or call can be the left operand of an operator; assign the `(stm, exp)` to a name first";
+
+// A hand-written parser for the language the SLR table in grammar.rs parses. It
+// builds the same tree gen_line does, spans included, with node ids from `ids`,
+// so either can feed codegen and --parser=compare can hold one against the
+// other. Statements are parsed by recursive descent and expressions Pratt-style:
+// a new statement is a new arm in `stm`, a new operator a new row in
+// `binary_op`.
+//
+// Errors say what was being parsed when the wrong token came. The statement is
+// then dropped as Stm::Error and input is skipped to a `;`, `}` or the end of the
+// file, as the SLR parser does.
+pub fn parse_descent(
+    lexer: &mut Lexer,
+    token_errors: &mut Vec<TokenError>,
+    errors: &mut Vec<ParseError>,
+    ids: &mut NodeIds,
+) -> Stm {
+    let mut parser = Descent {
+        lexer,
+        token_errors,
+        errors,
+        ids,
+        recovering: false,
+        last: Span::default(),
+    };
+    parser.stm_list("EndOfFile")
+}
+
+// The operators: the name gen_line gives each and its binding power. The grammar
+// gives them all the same precedence and groups them to the right, so `a - b - c`
+// is `a - (b - c)` and `a * b + c` is `a * (b + c)`.
+fn binary_op(tok: &Token) -> Option<(&'static str, u8)> {
+    match tok {
+        Token::Add(_) => Some(("add", 1)),
+        Token::Subtract(_) => Some(("sub", 1)),
+        Token::Multiply(_) => Some(("mul", 1)),
+        Token::Divide(_) => Some(("div", 1)),
+        _ => None,
+    }
+}
+
+struct Descent<'p, 'a> {
+    lexer: &'p mut Lexer<'a>,
+    token_errors: &'p mut Vec<TokenError>,
+    errors: &'p mut Vec<ParseError>,
+    ids: &'p mut NodeIds,
+    // true from an error until the next token is taken; an error in between is
+    // part of the same mistake and isn't reported again
+    recovering: bool,
+    // the last token taken, where the node being built ends so far
+    last: Span,
+}
+
+impl<'p, 'a> Descent<'p, 'a> {
+    fn peek(&mut self) -> &Token {
+        peek_token(self.lexer, self.token_errors)
+    }
+
+    fn at(&mut self, name: &str) -> bool {
+        self.peek().name() == name
+    }
+
+    // Takes the lookahead token. Never called on EndOfFile.
+    fn advance(&mut self) -> Token {
+        let tok = self.peek().clone();
+        self.lexer.next();
+        self.recovering = false;
+        self.last = tok.span().clone();
+        tok
+    }
+
+    // From `start` to the end of the last token taken.
+    fn extent(&self, start: &Span) -> Span {
+        start.to(&self.last)
+    }
+
+    // Takes a token of kind `name`, or fails saying what it was wanted for.
+    fn expect(&mut self, name: &str, context: &str) -> Result<Token, ParseError> {
+        if self.at(name) {
+            Ok(self.advance())
+        } else {
+            Err(self.expected(format!("{} {}", describe_token(name), context)))
+        }
+    }
+
+    fn expected(&mut self, what: String) -> ParseError {
+        ParseError::Expected(what, self.peek().clone(), None)
+    }
+
+    fn report(&mut self, err: ParseError) {
+        if !self.recovering {
+            self.errors.push(err);
+        }
+        self.recovering = true;
+    }
+
+    // Reports `err` and skips to where the statement after the failed one could
+    // start, which stands in for it.
+    fn recover(&mut self, err: ParseError) -> Stm {
+        let span = err.span().clone();
+        self.report(err);
+        self.skip_statement();
+        let node_id = self.ids.fresh();
+        Stm::Error(ErrorStm { span, node_id })
+    }
+
+    // Statements separated by `;`, up to `end`, which is left for the caller.
+    // They group to the left, as `stm -> stm ; stm` does in the table.
+    fn stm_list(&mut self, end: &str) -> Stm {
+        let mut prog = self.stm_or_error();
+        loop {
+            if self.at("Semicolon") {
+                self.advance();
+                let next = if self.at("RightCurl") || self.at("EndOfFile") {
+                    let found = self.peek().clone();
+                    let what = String::from("a statement after `;`");
+                    self.recover(ParseError::Expected(what, found, Some(TRAILING_SEMICOLON)))
+                } else {
+                    self.stm_or_error()
+                };
+                prog = self.compound(prog, next);
+            } else if self.at(end) {
+                return prog;
+            } else if self.at("EndOfFile") {
+                let err = self.expected(String::from("`}` to close the loop body"));
+                self.report(err);
+                return prog;
+            } else {
+                let what = format!("`;` or {}", describe_token(end));
+                let found = self.peek().clone();
+                let hint = Some("statements are separated by `;`");
+                self.report(ParseError::Expected(what, found, hint));
+                self.lexer.next();
+                self.skip_statement();
+            }
+        }
+    }
+
+    // Skips input up to a `;`, `}` or the end of the file, without taking it.
+    fn skip_statement(&mut self) {
+        while !matches!(
+            self.peek(),
+            Token::Semicolon(_) | Token::RightCurl(_) | Token::EndOfFile(_)
+        ) {
+            self.lexer.next();
+        }
+    }
+
+    fn stm_or_error(&mut self) -> Stm {
+        match self.stm() {
+            Ok(stm) => stm,
+            Err(err) => self.recover(err),
+        }
+    }
+
+    fn stm(&mut self) -> Result<Stm, ParseError> {
+        let tok = self.peek().clone();
+        match tok {
+            Token::ID(..) => Ok(Stm::Assign(self.assign("to assign to")?)),
+            Token::I64(span) => {
+                self.advance();
+                let mut assign = self.assign("to declare after `i64`")?;
+                assign.i64_decl = true;
+                assign.span = span.to(&assign.span);
+                Ok(Stm::Assign(assign))
+            }
+            Token::Print(_) => self.print(true, false),
+            Token::Write(_) => self.print(false, false),
+            Token::PrintChar(_) => self.print(false, true),
+            Token::For(_) => self.for_stm(),
+            Token::Import(span) => {
+                self.advance();
+                let module = self.expect("ID", "to import")?.val();
+                Ok(Stm::Import(ImportStm {
+                    module,
+                    span: self.extent(&span),
+                    node_id: self.ids.fresh(),
+                }))
+            }
+            Token::Export(span) => {
+                self.advance();
+                let id = self.expect("ID", "to export")?.val();
+                Ok(Stm::Export(ExportStm {
+                    id,
+                    span: self.extent(&span),
+                    node_id: self.ids.fresh(),
+                }))
+            }
+            Token::Asm(span) => self.asm(span),
+            Token::Const(span) => {
+                self.advance();
+                let id = self.expect("ID", "after `const`")?.val();
+                self.expect("Assign", &format!("after `{}`", id))?;
+                let exp = self.exp("after `:=`")?;
+                Ok(Stm::Const(ConstStm {
+                    id,
+                    exp,
+                    span: self.extent(&span),
+                    node_id: self.ids.fresh(),
+                }))
+            }
+            _ => Err(self.expected(String::from("a statement"))),
+        }
+    }
+
+    // Statements separated by `;` inside an `(stm, exp)`. An error there fails
+    // the whole expression, so these don't recover one by one.
+    fn stm_sequence(&mut self) -> Result<Stm, ParseError> {
+        let mut stm = self.stm()?;
+        while self.at("Semicolon") {
+            self.advance();
+            let next = self.stm()?;
+            stm = self.compound(stm, next);
+        }
+        Ok(stm)
+    }
+
+    fn assign(&mut self, context: &str) -> Result<AssignStm, ParseError> {
+        let id = self.expect("ID", context)?;
+        self.expect("Assign", &format!("after `{}`", id.val()))?;
+        let exp = self.exp("after `:=`")?;
+        Ok(AssignStm {
+            id: Box::new(Exp::Id(IdExp {
+                id: id.val(),
+                span: id.span().clone(),
+                node_id: self.ids.fresh(),
+            })),
+            exp,
+            i64_decl: false,
+            span: self.extent(id.span()),
+            node_id: self.ids.fresh(),
+        })
+    }
+
+    fn print(&mut self, newline: bool, chars: bool) -> Result<Stm, ParseError> {
+        let start = self.advance();
+        let keyword = describe_token(start.name());
+        self.expect("LeftParen", &format!("after {}", keyword))?;
+        let exps = self.exp_list(&format!("as an argument of {}", keyword))?;
+        self.expect(
+            "RightParen",
+            &format!("to close the arguments of {}", keyword),
+        )?;
+        Ok(Stm::Print(PrintStm {
+            exps,
+            newline,
+            chars,
+            span: self.extent(start.span()),
+            node_id: self.ids.fresh(),
+        }))
+    }
+
+    fn for_stm(&mut self) -> Result<Stm, ParseError> {
+        let start = self.advance();
+        self.expect("LeftParen", "after `for`")?;
+        let iter = self.assign("to start the `for` loop with")?;
+        self.expect("Semicolon", "after the first assignment of the `for` loop")?;
+        let left = self.exp("as the `for` condition")?;
+        let op = match self.peek() {
+            Token::LessThan(_) => 0,
+            Token::GreaterThan(_) => 1,
+            _ => return Err(self.expected(String::from("`<` or `>` in the `for` condition"))),
+        };
+        let op_tok = self.advance();
+        let right = self.exp(&format!("after {}", op_tok.describe()))?;
+        let comp = CompExp {
+            span: left.span().to(right.span()),
+            left,
+            right,
+            op,
+            node_id: self.ids.fresh(),
+        };
+        self.expect("Semicolon", "after the `for` condition")?;
+        let inc = self.assign("to update at the end of each `for` loop")?;
+        self.expect("RightParen", "to close the `for` header")?;
+        self.expect("LeftCurl", "before the `for` loop body")?;
+        let body = self.stm_list("RightCurl");
+        // at the end of the file instead, stm_list has said the `}` is missing
+        if self.at("RightCurl") {
+            self.advance();
+        }
+        Ok(Stm::For(ForStm {
+            iter: Box::new(Stm::Assign(iter)),
+            comp: Box::new(Exp::Comp(comp)),
+            inc: Box::new(Stm::Assign(inc)),
+            body: Box::new(body),
+            span: self.extent(start.span()),
+            node_id: self.ids.fresh(),
+        }))
+    }
+
+    fn asm(&mut self, span: Span) -> Result<Stm, ParseError> {
+        self.advance();
+        self.expect("LeftParen", "after `asm`")?;
+        let template = self.expect("Str", "as the `asm` template")?.val();
+        let mut operands = Vec::new();
+        while self.at("Comma") {
+            self.advance();
+            operands.push(self.asm_operand()?);
+        }
+        self.expect("RightParen", "to close `asm`")?;
+        Ok(Stm::Asm(AsmStm {
+            template,
+            operands,
+            span: self.extent(&span),
+            node_id: self.ids.fresh(),
+        }))
+    }
+
+    fn asm_operand(&mut self) -> Result<AsmOperand, ParseError> {
+        let tok = self.peek().clone();
+        let operand = match tok {
+            Token::Out(_) => {
+                self.advance();
+                self.expect("LeftParen", "after `out`")?;
+                let id = self.expect("ID", "for `out` to write to")?;
+                AsmOperand::Out(Box::new(Exp::Id(IdExp {
+                    id: id.val(),
+                    span: id.span().clone(),
+                    node_id: self.ids.fresh(),
+                })))
+            }
+            Token::In(_) => {
+                self.advance();
+                self.expect("LeftParen", "after `in`")?;
+                AsmOperand::In(self.exp("as the value of `in`")?)
+            }
+            Token::Clobber(_) => {
+                self.advance();
+                self.expect("LeftParen", "after `clobber`")?;
+                AsmOperand::Clobber(self.expect("Str", "naming the register")?.val())
+            }
+            _ => return Err(self.expected(String::from("`out`, `in` or `clobber` in `asm`"))),
+        };
+        self.expect("RightParen", &format!("to close {}", tok.describe()))?;
+        Ok(operand)
+    }
+
+    // Arguments of print, write, print_char or a call. Strings are only allowed
+    // here, as whole arguments.
+    fn exp_list(&mut self, context: &str) -> Result<Box<ExpList>, ParseError> {
+        let head = match self.peek() {
+            Token::Str(value, span) => {
+                let (value, span) = (value.clone(), span.clone());
+                self.advance();
+                Box::new(Exp::Str(StrExp {
+                    value,
+                    span,
+                    node_id: self.ids.fresh(),
+                }))
+            }
+            _ => self.exp(context)?,
+        };
+        if self.at("Comma") {
+            self.advance();
+            let tail = self.exp_list(context)?;
+            Ok(Box::new(ExpList::Pair(PairExpList {
+                span: head.span().to(tail.span()),
+                head,
+                tail,
+                node_id: self.ids.fresh(),
+            })))
+        } else {
+            Ok(Box::new(ExpList::Last(LastExpList {
+                span: head.span().clone(),
+                head,
+                node_id: self.ids.fresh(),
+            })))
+        }
+    }
+
+    fn exp(&mut self, context: &str) -> Result<Box<Exp>, ParseError> {
+        self.exp_from(0, context)
+    }
+
+    // An operand followed by operators that bind at least as tightly as
+    // `min_power`. A right operand is parsed with its operator's own power, so
+    // operators of equal power group to the right.
+    fn exp_from(&mut self, min_power: u8, context: &str) -> Result<Box<Exp>, ParseError> {
+        let mut left = self.operand(context)?;
+        while let Some((op, power)) = binary_op(self.peek()) {
+            if power < min_power {
+                break;
+            }
+            if let Exp::Eseq(_) = *left {
+                let found = self.peek().clone();
+                let what = String::from("the end of the expression");
+                return Err(ParseError::Expected(what, found, Some(ESEQ_OPERAND)));
+            }
+            let op_tok = self.advance();
+            let right = self.exp_from(power, &format!("after {}", op_tok.describe()))?;
+            left = Box::new(Exp::Op(OpExp {
+                span: left.span().to(right.span()),
+                left,
+                right,
+                op: op.to_string(),
+                node_id: self.ids.fresh(),
+            }));
+        }
+        Ok(left)
+    }
+
+    // A name, number, character, call or `(stm, exp)`.
+    fn operand(&mut self, context: &str) -> Result<Box<Exp>, ParseError> {
+        let tok = self.peek().clone();
+        match tok {
+            Token::ID(id, span) => {
+                self.advance();
+                if !self.at("LeftParen") {
+                    let exp = Exp::Id(IdExp {
+                        id,
+                        span,
+                        node_id: self.ids.fresh(),
+                    });
+                    return Ok(Box::new(exp));
+                }
+                self.advance();
+                let args = self.exp_list(&format!("as an argument of `{}`", id))?;
+                self.expect("RightParen", &format!("to close the arguments of `{}`", id))?;
+                let exp = Exp::Call(CallExp {
+                    name: id,
+                    args,
+                    span: self.extent(&span),
+                    node_id: self.ids.fresh(),
+                });
+                Ok(Box::new(exp))
+            }
+            // character literals are plain numbers from here on, as in gen_line
+            Token::Num(..) | Token::Char(..) => {
+                self.advance();
+                let exp = Exp::Num(NumExp {
+                    num: tok.val(),
+                    span: tok.span().clone(),
+                    node_id: self.ids.fresh(),
+                });
+                Ok(Box::new(exp))
+            }
+            Token::LeftParen(span) => {
+                self.advance();
+                let stm = Box::new(self.stm_sequence()?);
+                self.expect("Comma", "after the statement of an `(stm, exp)`")?;
+                let exp = self.exp("after the `,` of an `(stm, exp)`")?;
+                self.expect("RightParen", "to close the `(stm, exp)`")?;
+                Ok(Box::new(Exp::Eseq(EseqExp {
+                    stm,
+                    exp,
+                    span: self.extent(&span),
+                    node_id: self.ids.fresh(),
+                })))
+            }
+            _ => Err(self.expected(format!("an expression {}", context))),
+        }
+    }
+
+    fn compound(&mut self, stm1: Stm, stm2: Stm) -> Stm {
+        Stm::Compound(CompoundStm {
+            span: stm1.span().to(stm2.span()),
+            stm1: Box::new(stm1),
+            stm2: Box::new(stm2),
+            node_id: self.ids.fresh(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use crate::ast::{stm_difference, Stm};
+    use crate::cst::{SyntaxElement, SyntaxNode};
+    use crate::grammar::{ParseTable, PRODUCTIONS};
+    use crate::test_support::{parse_str_descent, parse_str_slr, table};
+
+    // Programs that between them reduce by every production.
+    const CORPUS: [&str; 10] = [
+        "x := 1;\ny := x + 2 * 3 - 4 / 2;\nprint(x, y)",
+        "write(\"a\", 1, \"b\");\nprint(\"done\")",
+        "c := 'A' + 1;\nprint_char('B', c, '\\n')",
+        "i64 big := 3000000000;\nbig := big * big - 1",
+        "import lib;\nexport v;\nv := lib.x + 1",
+        "asm(\"move %0, %1\", out(r), in(s + 1), clobber(\"$s0\"));\nasm(\"nop\")",
+        "const N := 10 * 4;\nx := abs(N) + max(1, 2) * 3;\nprint(pow(2, N))",
+        "x := (y := 2, y + 1);\nz := 1 + (w := (v := 3, v), w)",
+        "for (i := 0; i < 3; i := i + 1) {\n  for (j := 3; j > i; j := j - 1) {\n    print(i, j)\n  }\n}",
+        "\tin := 1;\r\nout := in;\r\nasm(\"move %0, %1\", out(out), in(in))\r\n",
+    ];
+
+    // Programs with errors, which both parsers must reject at the same places.
+    const ERRORS: [&str; 9] = [
+        "a := ;\nb := 1",
+        "x := 1\ny := 2",
+        "x := 1;",
+        "print(1;)",
+        "for (i := 0; i 3; i := i + 1) { print(i) }",
+        "x := (y := 1, y) + 2",
+        "asm(\"nop\", out(1))",
+        "a := 1 +;\nb := ;\nprint(a b)",
+        "x := 1 @ 2;\nprint(x",
+    ];
+
+    // The tree, if any, and where each error was found, as (line, col).
+    fn parse_slr(
+        table: &ParseTable,
+        source: &str,
+        prods: &mut BTreeSet<usize>,
+    ) -> (Option<Stm>, Vec<(usize, usize)>) {
+        let parsed = parse_str_slr(table, source);
+        let found = parsed.error_positions();
+        match parsed.prog {
+            Some((tree, prog)) => {
+                collect_prods(&tree.prog, prods);
+                (Some(prog), found)
+            }
+            None => (None, found),
+        }
+    }
+
+    fn parse_recursive(source: &str) -> (Stm, Vec<(usize, usize)>) {
+        let parsed = parse_str_descent(source);
+        let found = parsed.error_positions();
+        (parsed.prog, found)
+    }
+
+    fn collect_prods(node: &SyntaxNode, prods: &mut BTreeSet<usize>) {
+        prods.insert(node.prod);
+        for child in &node.children {
+            if let SyntaxElement::Node(child) = child {
+                collect_prods(child, prods);
+            }
+        }
+    }
+
+    #[test]
+    fn parsers_build_the_same_trees() {
+        let table = table();
+        let mut prods = BTreeSet::new();
+        for source in CORPUS {
+            let (slr, slr_errors) = parse_slr(&table, source, &mut prods);
+            let (descent, descent_errors) = parse_recursive(source);
+            assert!(
+                slr_errors.is_empty() && descent_errors.is_empty(),
+                "{:?} was rejected",
+                source
+            );
+            let slr = slr.unwrap_or_else(|| panic!("no SLR tree for {:?}", source));
+            if let Some((a, b)) = stm_difference(&slr, &descent) {
+                panic!("{:?}: SLR built `{}`, descent built `{}`", source, a, b);
+            }
+        }
+        for source in ERRORS {
+            parse_slr(&table, source, &mut prods);
+        }
+        let missing: Vec<usize> = PRODUCTIONS
+            .iter()
+            .map(|prod| prod.num)
+            .filter(|num| !prods.contains(num))
+            .collect();
+        assert!(
+            missing.is_empty(),
+            "productions {:?} never reduced",
+            missing
+        );
+    }
+
+    // Recovery can leave different trees, since the SLR parser may drop a
+    // statement it had finished along with the one the error is in, so only the
+    // errors are compared.
+    #[test]
+    fn parsers_report_the_same_errors() {
+        let table = table();
+        for source in ERRORS {
+            let (_, slr_errors) = parse_slr(&table, source, &mut BTreeSet::new());
+            let (_, descent_errors) = parse_recursive(source);
+            assert!(!slr_errors.is_empty(), "{:?} was accepted", source);
+            assert_eq!(slr_errors, descent_errors, "{:?}", source);
+        }
+    }
+}
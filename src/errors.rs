@@ -1,56 +1,384 @@
 use std::fmt;
+use std::path::PathBuf;
 
-use crate::lex::Token;
+use crate::intrinsics;
+use crate::lex::{describe_token, Span, Token};
 
 pub enum TokenError {
-    InvalidCharacter(char),
+    InvalidCharacter(char, Span),
+    UnterminatedString(Span),
+    InvalidEscape(char, Span),
+    InvalidCharLiteral(Span),
+    MalformedNumber(String, Span),
+    NumberOutOfRange(String, u32, Span),
+    ExtraDecimalPoint(String, Span),
+    MissingFraction(String, Span),
+    InvalidDigit(String, char, Span),
+    DanglingColon(Span),
+    Unreadable(String, Span),
+}
+
+impl TokenError {
+    // SourceMap::locate_span turns this into file:line:col
+    pub fn span(&self) -> &Span {
+        match self {
+            TokenError::InvalidCharacter(_, span)
+            | TokenError::UnterminatedString(span)
+            | TokenError::InvalidEscape(_, span)
+            | TokenError::InvalidCharLiteral(span)
+            | TokenError::MalformedNumber(_, span)
+            | TokenError::NumberOutOfRange(_, _, span)
+            | TokenError::ExtraDecimalPoint(_, span)
+            | TokenError::MissingFraction(_, span)
+            | TokenError::InvalidDigit(_, _, span)
+            | TokenError::DanglingColon(span)
+            | TokenError::Unreadable(_, span) => span,
+        }
+    }
 }
 
 impl fmt::Display for TokenError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            TokenError::InvalidCharacter(c) => write!(f, "Invalid input found: {}", c),
+        match self {
+            TokenError::InvalidCharacter(c, _) => write!(f, "Invalid input found: {}", c),
+            TokenError::UnterminatedString(_) => write!(f, "Unterminated string literal"),
+            TokenError::InvalidEscape(c, _) => write!(f, "Invalid escape sequence \\{}", c),
+            TokenError::InvalidCharLiteral(_) => {
+                write!(f, "Character literal must hold exactly one character")
+            }
+            TokenError::MalformedNumber(num, _) => write!(f, "Malformed number {}", num),
+            TokenError::NumberOutOfRange(num, bits, _) => {
+                write!(f, "Number {} does not fit in a {}-bit integer", num, bits)
+            }
+            TokenError::ExtraDecimalPoint(num, _) => {
+                write!(f, "Number {} has more than one decimal point", num)
+            }
+            TokenError::MissingFraction(num, _) => {
+                write!(f, "Number {} needs digits after the decimal point", num)
+            }
+            TokenError::InvalidDigit(num, digit, _) => {
+                write!(f, "Number {} contains the invalid digit {}", num, digit)
+            }
+            TokenError::DanglingColon(_) => {
+                write!(f, "':' must be followed by '=' to form :=")
+            }
+            TokenError::Unreadable(why, _) => write!(f, "couldn't read source: {}", why),
+        }
+    }
+}
+
+// Preprocessor errors already carry a file:line location.
+pub enum PreprocessError {
+    CouldNotRead(String, String),
+    IncludeCycle(Vec<String>),
+    MalformedDirective(String, String),
+    InvalidMacroName(String, String),
+    UnmatchedDirective(&'static str, String),
+    UnterminatedConditional(String),
+}
+
+impl fmt::Display for PreprocessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PreprocessError::CouldNotRead(file, why) => {
+                write!(f, "couldn't open {}: {}", file, why)
+            }
+            PreprocessError::IncludeCycle(chain) => {
+                write!(f, "#include cycle: {}", chain.join(" -> "))
+            }
+            PreprocessError::MalformedDirective(directive, location) => {
+                write!(f, "{}: malformed directive {}", location, directive)
+            }
+            PreprocessError::InvalidMacroName(name, location) => {
+                write!(f, "{}: invalid macro name \"{}\"", location, name)
+            }
+            PreprocessError::UnmatchedDirective(directive, location) => {
+                write!(f, "{}: {} without #ifdef or #ifndef", location, directive)
+            }
+            PreprocessError::UnterminatedConditional(file) => {
+                write!(f, "{}: #ifdef or #ifndef is missing its #endif", file)
+            }
         }
     }
 }
 
-pub enum ParseError<'a> {
-    UnexpectedToken(&'a Token, &'a str, &'a usize),
+pub enum ModuleError {
+    NotFound(String, Vec<PathBuf>),
+    ImportCycle(Vec<String>),
+    NotImported(String),
+    NotExported(String),
+    InvalidName(String),
+    // --parser=compare: the first subtrees where the SLR and recursive-descent
+    // parsers' trees differ
+    ParsersDisagree(String, String),
+    // --parser=compare: the parser that rejected a file the other accepted
+    ParserRejected(&'static str),
 }
 
-impl<'a> fmt::Display for ParseError<'a> {
+impl fmt::Display for ModuleError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            ParseError::UnexpectedToken(tok, err_mess, pos) => {
+        match self {
+            ModuleError::NotFound(module, search_path) => {
+                let dirs: Vec<String> = search_path
+                    .iter()
+                    .map(|dir| dir.display().to_string())
+                    .collect();
                 write!(
                     f,
-                    "Unexpected token at line {}, pos in token stream: {}: {} {}",
-                    tok.line(),
-                    pos,
-                    tok.name(),
-                    err_mess
+                    "module {} not found; looked for {}.slp in: {}",
+                    module,
+                    module,
+                    dirs.join(", ")
                 )
             }
+            ModuleError::ImportCycle(chain) => write!(f, "import cycle: {}", chain.join(" -> ")),
+            ModuleError::NotImported(name) => {
+                write!(f, "{} refers to a module that is not imported here", name)
+            }
+            ModuleError::NotExported(name) => write!(f, "{} is not exported", name),
+            ModuleError::InvalidName(name) => write!(f, "invalid name {}", name),
+            ModuleError::ParsersDisagree(slr, descent) => write!(
+                f,
+                "the parsers built different trees: `{}` from the SLR parser, `{}` from the recursive-descent parser",
+                slr, descent
+            ),
+            ModuleError::ParserRejected(parser) => {
+                write!(f, "only the {} parser rejected this file", parser)
+            }
         }
     }
 }
 
-pub fn throw_err<'a>(curr_tok: &'a Token, pos: &'a usize) -> ParseError<'a> {
-    let mut err_message = "";
-    match curr_tok {
-        Token::EndOfFile(_) => {
-            err_message = ": Potential problem: statement has unneeded semicolon at end of program or for-loop";
+pub enum CompileError {
+    I64Division(Span),
+    DecimalLiteral(String, Span),
+    // a decimal literal beyond 32 bits outside an i64 expression
+    WideLiteral(String, Span),
+    FixedPointRange(String, Span),
+    I64FixedPoint,
+    AsmOperand(String, Span),
+    AsmClobber(String, Span),
+    AsmI64(Span),
+    AsmRegisters(Span),
+    UnknownFunction(String, Span),
+    IntrinsicArity(String, usize, usize, Span),
+    IntrinsicArgument(String, Span),
+    IntrinsicFixedPoint(String, Span),
+    ConstValue(String, &'static str, Span),
+    ConstAssignment(String, Span),
+    ConstDeclaration(String, Span),
+    ConstRedeclaration(String, Span),
+    Unparsed(Span),
+    // a read of a variable nothing has assigned yet, or that only a loop that
+    // may not run has assigned
+    Uninitialized(String, Span),
+    MaybeUninitialized(String, Span),
+}
+
+impl CompileError {
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            CompileError::I64Division(span)
+            | CompileError::DecimalLiteral(_, span)
+            | CompileError::WideLiteral(_, span)
+            | CompileError::FixedPointRange(_, span)
+            | CompileError::AsmOperand(_, span)
+            | CompileError::AsmClobber(_, span)
+            | CompileError::AsmI64(span)
+            | CompileError::AsmRegisters(span)
+            | CompileError::UnknownFunction(_, span)
+            | CompileError::IntrinsicArity(_, _, _, span)
+            | CompileError::IntrinsicArgument(_, span)
+            | CompileError::IntrinsicFixedPoint(_, span)
+            | CompileError::ConstValue(_, _, span)
+            | CompileError::ConstAssignment(_, span)
+            | CompileError::ConstDeclaration(_, span)
+            | CompileError::ConstRedeclaration(_, span)
+            | CompileError::Unparsed(span)
+            | CompileError::Uninitialized(_, span)
+            | CompileError::MaybeUninitialized(_, span) => Some(span),
+            CompileError::I64FixedPoint => None,
+        }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompileError::I64Division(_) => {
+                write!(f, "Division of i64 values is not supported")
+            }
+            CompileError::DecimalLiteral(num, _) => {
+                write!(f, "Decimal literal {} needs --fixed-point", num)
+            }
+            CompileError::WideLiteral(num, _) => write!(
+                f,
+                "Number {} does not fit in a 32-bit integer; only an i64 expression can use it",
+                num
+            ),
+            CompileError::FixedPointRange(num, _) => write!(
+                f,
+                "Literal {} is outside the Q16.16 range -32768 to 32767",
+                num
+            ),
+            CompileError::I64FixedPoint => {
+                write!(f, "i64 variables cannot be used with --fixed-point")
+            }
+            CompileError::AsmOperand(operand, _) => {
+                write!(f, "asm template refers to missing operand {}", operand)
+            }
+            CompileError::AsmClobber(reg, _) => {
+                write!(f, "asm clobber {} is not a register", reg)
+            }
+            CompileError::AsmI64(_) => write!(f, "asm operands cannot be i64 values"),
+            CompileError::AsmRegisters(_) => {
+                write!(f, "asm statement needs more registers than are free")
+            }
+            CompileError::UnknownFunction(name, _) => write!(
+                f,
+                "Unknown function {}; the built-ins are {}",
+                name,
+                intrinsics::names().join(", ")
+            ),
+            CompileError::IntrinsicArity(name, expected, found, _) => write!(
+                f,
+                "{} takes {} argument{}, found {}",
+                name,
+                expected,
+                if *expected == 1 { "" } else { "s" },
+                found
+            ),
+            CompileError::IntrinsicArgument(name, _) => {
+                write!(f, "Arguments to {} must be 32-bit numbers", name)
+            }
+            CompileError::IntrinsicFixedPoint(name, _) => {
+                write!(f, "{} cannot be used with --fixed-point", name)
+            }
+            CompileError::ConstValue(name, reason, _) => write!(f, "Constant {} {}", name, reason),
+            CompileError::ConstAssignment(name, _) => {
+                write!(f, "Cannot assign to constant {}", name)
+            }
+            CompileError::ConstDeclaration(name, _) => write!(
+                f,
+                "Constant {} must be declared before any other use of the name",
+                name
+            ),
+            CompileError::ConstRedeclaration(name, _) => {
+                write!(f, "Constant {} is already declared", name)
+            }
+            CompileError::Unparsed(_) => {
+                write!(f, "Statement was dropped after a syntax error")
+            }
+            CompileError::Uninitialized(name, _) => {
+                write!(f, "Variable {} is read before it is assigned", name)
+            }
+            CompileError::MaybeUninitialized(name, _) => write!(
+                f,
+                "Variable {} may not be assigned yet; only a `for` loop that may not run assigns it",
+                name
+            ),
         }
-        Token::RightCurl(_) => {
-            err_message = ": Potential problem: empty For-block not allowed or unneeded semicolon";
+    }
+}
+
+pub enum ParseError {
+    // the token, the kinds of token the parser could have taken there, and a
+    // one-token fix that would let the parse go on, if there is one
+    UnexpectedToken(Token, Vec<String>, Option<Repair>),
+    // from the recursive-descent parser: what it was looking for, in words, the
+    // token it found instead, and why that token doesn't fit when it isn't obvious
+    Expected(String, Token, Option<&'static str>),
+    // the parse table and gen_line disagree about a production
+    InternalGrammar(usize, String, Span),
+}
+
+pub enum Repair {
+    Insert(String),
+    Delete,
+}
+
+impl ParseError {
+    pub fn span(&self) -> &Span {
+        match self {
+            ParseError::UnexpectedToken(tok, _, _) | ParseError::Expected(_, tok, _) => tok.span(),
+            ParseError::InternalGrammar(_, _, span) => span,
         }
-        Token::ID(val, _) => {
-            err_message = val;
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::UnexpectedToken(tok, expected, repair) => {
+                let expected: Vec<&str> = expected.iter().map(|t| describe_token(t)).collect();
+                match expected.split_last() {
+                    Some((last, [])) => write!(f, "expected {}, ", last)?,
+                    Some((last, rest)) => write!(f, "expected {} or {}, ", rest.join(", "), last)?,
+                    None => {}
+                }
+                write!(f, "found {}", tok.describe())?;
+                match repair {
+                    Some(Repair::Insert(name)) => {
+                        write!(f, "; try inserting {} before it", describe_token(name))
+                    }
+                    Some(Repair::Delete) => write!(f, "; try removing it"),
+                    None => Ok(()),
+                }
+            }
+            ParseError::Expected(expected, tok, hint) => {
+                write!(f, "expected {}, found {}", expected, tok.describe())?;
+                match hint {
+                    Some(hint) => write!(f, "; {}", hint),
+                    None => Ok(()),
+                }
+            }
+            ParseError::InternalGrammar(prod, why, _) => {
+                write!(f, "Internal grammar error in production {}: {}", prod, why)
+            }
         }
-        Token::Num(val, _) => {
-            err_message = val;
+    }
+}
+
+// A conflict the parse table generator couldn't settle: two actions for one
+// lookahead in one state, and the LR(0) items that state is made of. A table
+// read from a file instead fails with the file:line it is wrong at.
+pub enum GrammarError {
+    Conflict {
+        state: usize,
+        lookahead: String,
+        actions: (String, String),
+        items: Vec<String>,
+    },
+    InvalidTable(String, String),
+}
+
+impl fmt::Display for GrammarError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GrammarError::Conflict {
+                state,
+                lookahead,
+                actions: (existing, reduce),
+                items,
+            } => {
+                let kind = if existing.starts_with('s') {
+                    "shift/reduce"
+                } else {
+                    "reduce/reduce"
+                };
+                write!(
+                    f,
+                    "{} conflict in state {} on {}: {} or {}",
+                    kind, state, lookahead, existing, reduce
+                )?;
+                for item in items {
+                    write!(f, "\n    {}", item)?;
+                }
+                Ok(())
+            }
+            GrammarError::InvalidTable(location, message) => {
+                write!(f, "{}: {}", location, message)
+            }
         }
-        _ => {}
     }
-    return ParseError::UnexpectedToken(curr_tok, err_message, pos);
 }
@@ -0,0 +1,803 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+
+use crate::errors::GrammarError;
+use crate::ir_code_gen::{self as actions, Action};
+
+// One rule of the grammar and the action that builds its AST value when the
+// parser reduces by it. A production that only passes its one value up, such as
+// `exp -> opexp`, has no action. `num` is the production's place in PRODUCTIONS,
+// counting from 1, which parse tables refer to it by.
+pub struct Production {
+    pub num: usize,
+    pub lhs: &'static str,
+    pub rhs: &'static [&'static str],
+    pub action: Option<Action>,
+}
+
+// Token::name() of every token the lexer can produce.
+pub const TERMINALS: [&str; 30] = [
+    "ID",
+    "Print",
+    "Num",
+    "Semicolon",
+    "Assign",
+    "RightParen",
+    "LeftParen",
+    "RightCurl",
+    "LeftCurl",
+    "Comma",
+    "Add",
+    "Subtract",
+    "Multiply",
+    "Divide",
+    "EndOfFile",
+    "For",
+    "LessThan",
+    "GreaterThan",
+    "Write",
+    "Str",
+    "Char",
+    "PrintChar",
+    "I64",
+    "Import",
+    "Export",
+    "Asm",
+    "Out",
+    "In",
+    "Clobber",
+    "Const",
+];
+
+// A terminal the lexer never produces. On a syntax error the parser pretends to
+// see one, so `stm -> error` can stand in for the statement that failed.
+pub const ERROR: &str = "error";
+
+// The first one is the start symbol.
+pub const NONTERMINALS: [&str; 14] = [
+    "prog",
+    "stm",
+    "assignstm",
+    "explist",
+    "exp",
+    "compexp",
+    "opexp",
+    "eseqexp",
+    "binop",
+    "name",
+    "text",
+    "asmops",
+    "asmop",
+    "call",
+];
+
+pub const PRODUCTIONS: [Production; 44] = [
+    Production {
+        num: 1,
+        lhs: "prog",
+        rhs: &["stm"],
+        action: None,
+    },
+    Production {
+        num: 2,
+        lhs: "stm",
+        rhs: &["stm", "Semicolon", "stm"],
+        action: Some(actions::compound_stm),
+    },
+    Production {
+        num: 3,
+        lhs: "stm",
+        rhs: &["assignstm"],
+        action: None,
+    },
+    Production {
+        num: 4,
+        lhs: "stm",
+        rhs: &["Print", "LeftParen", "explist", "RightParen"],
+        action: Some(actions::print_stm),
+    },
+    Production {
+        num: 5,
+        lhs: "stm",
+        rhs: &[
+            "For",
+            "LeftParen",
+            "assignstm",
+            "Semicolon",
+            "compexp",
+            "Semicolon",
+            "assignstm",
+            "RightParen",
+            "LeftCurl",
+            "prog",
+            "RightCurl",
+        ],
+        action: Some(actions::for_stm),
+    },
+    Production {
+        num: 6,
+        lhs: "assignstm",
+        rhs: &["ID", "Assign", "exp"],
+        action: Some(actions::assign_stm),
+    },
+    Production {
+        num: 7,
+        lhs: "explist",
+        rhs: &["exp", "Comma", "explist"],
+        action: Some(actions::pair_exp_list),
+    },
+    Production {
+        num: 8,
+        lhs: "explist",
+        rhs: &["exp"],
+        action: Some(actions::last_exp_list),
+    },
+    Production {
+        num: 9,
+        lhs: "exp",
+        rhs: &["ID"],
+        action: Some(actions::id_exp),
+    },
+    Production {
+        num: 10,
+        lhs: "exp",
+        rhs: &["Num"],
+        action: Some(actions::num_exp),
+    },
+    Production {
+        num: 11,
+        lhs: "exp",
+        rhs: &["opexp"],
+        action: None,
+    },
+    Production {
+        num: 12,
+        lhs: "exp",
+        rhs: &["eseqexp"],
+        action: None,
+    },
+    Production {
+        num: 13,
+        lhs: "compexp",
+        rhs: &["exp", "LessThan", "exp"],
+        action: Some(actions::less_than),
+    },
+    Production {
+        num: 14,
+        lhs: "compexp",
+        rhs: &["exp", "GreaterThan", "exp"],
+        action: Some(actions::greater_than),
+    },
+    Production {
+        num: 15,
+        lhs: "opexp",
+        rhs: &["ID", "binop", "exp"],
+        action: Some(actions::id_op_exp),
+    },
+    Production {
+        num: 16,
+        lhs: "opexp",
+        rhs: &["Num", "binop", "exp"],
+        action: Some(actions::num_op_exp),
+    },
+    Production {
+        num: 17,
+        lhs: "eseqexp",
+        rhs: &["LeftParen", "stm", "Comma", "exp", "RightParen"],
+        action: Some(actions::eseq_exp),
+    },
+    Production {
+        num: 18,
+        lhs: "binop",
+        rhs: &["Add"],
+        action: Some(actions::add),
+    },
+    Production {
+        num: 19,
+        lhs: "binop",
+        rhs: &["Subtract"],
+        action: Some(actions::sub),
+    },
+    Production {
+        num: 20,
+        lhs: "binop",
+        rhs: &["Multiply"],
+        action: Some(actions::mul),
+    },
+    Production {
+        num: 21,
+        lhs: "binop",
+        rhs: &["Divide"],
+        action: Some(actions::div),
+    },
+    Production {
+        num: 22,
+        lhs: "stm",
+        rhs: &["Write", "LeftParen", "explist", "RightParen"],
+        action: Some(actions::write_stm),
+    },
+    Production {
+        num: 23,
+        lhs: "explist",
+        rhs: &["Str", "Comma", "explist"],
+        action: Some(actions::str_pair_exp_list),
+    },
+    Production {
+        num: 24,
+        lhs: "explist",
+        rhs: &["Str"],
+        action: Some(actions::str_last_exp_list),
+    },
+    Production {
+        num: 25,
+        lhs: "exp",
+        rhs: &["Char"],
+        action: Some(actions::num_exp),
+    },
+    Production {
+        num: 26,
+        lhs: "opexp",
+        rhs: &["Char", "binop", "exp"],
+        action: Some(actions::num_op_exp),
+    },
+    Production {
+        num: 27,
+        lhs: "stm",
+        rhs: &["PrintChar", "LeftParen", "explist", "RightParen"],
+        action: Some(actions::print_char_stm),
+    },
+    Production {
+        num: 28,
+        lhs: "stm",
+        rhs: &["I64", "assignstm"],
+        action: Some(actions::i64_decl),
+    },
+    Production {
+        num: 29,
+        lhs: "stm",
+        rhs: &["Import", "name"],
+        action: Some(actions::import_stm),
+    },
+    Production {
+        num: 30,
+        lhs: "stm",
+        rhs: &["Export", "name"],
+        action: Some(actions::export_stm),
+    },
+    Production {
+        num: 31,
+        lhs: "name",
+        rhs: &["ID"],
+        action: Some(actions::token),
+    },
+    Production {
+        num: 32,
+        lhs: "stm",
+        rhs: &["Asm", "LeftParen", "text", "asmops", "RightParen"],
+        action: Some(actions::asm_stm),
+    },
+    Production {
+        num: 33,
+        lhs: "stm",
+        rhs: &["Asm", "LeftParen", "text", "RightParen"],
+        action: Some(actions::bare_asm_stm),
+    },
+    Production {
+        num: 34,
+        lhs: "asmops",
+        rhs: &["Comma", "asmop", "asmops"],
+        action: Some(actions::asm_operands),
+    },
+    Production {
+        num: 35,
+        lhs: "asmops",
+        rhs: &["Comma", "asmop"],
+        action: Some(actions::last_asm_operand),
+    },
+    Production {
+        num: 36,
+        lhs: "asmop",
+        rhs: &["Out", "LeftParen", "name", "RightParen"],
+        action: Some(actions::out_operand),
+    },
+    Production {
+        num: 37,
+        lhs: "asmop",
+        rhs: &["In", "LeftParen", "exp", "RightParen"],
+        action: Some(actions::in_operand),
+    },
+    Production {
+        num: 38,
+        lhs: "asmop",
+        rhs: &["Clobber", "LeftParen", "text", "RightParen"],
+        action: Some(actions::clobber_operand),
+    },
+    Production {
+        num: 39,
+        lhs: "text",
+        rhs: &["Str"],
+        action: Some(actions::token),
+    },
+    Production {
+        num: 40,
+        lhs: "exp",
+        rhs: &["call"],
+        action: None,
+    },
+    Production {
+        num: 41,
+        lhs: "call",
+        rhs: &["ID", "LeftParen", "explist", "RightParen"],
+        action: Some(actions::call_exp),
+    },
+    Production {
+        num: 42,
+        lhs: "opexp",
+        rhs: &["call", "binop", "exp"],
+        action: Some(actions::call_op_exp),
+    },
+    Production {
+        num: 43,
+        lhs: "stm",
+        rhs: &["Const", "name", "Assign", "exp"],
+        action: Some(actions::const_stm),
+    },
+    Production {
+        num: 44,
+        lhs: "stm",
+        rhs: &[ERROR],
+        action: Some(actions::error_stm),
+    },
+];
+
+// Numbering is checked when the compiler is built: a gap or a repeated number
+// fails the build here rather than leaving a table pointing at the wrong rule.
+const _: () = {
+    let mut i = 0;
+    while i < PRODUCTIONS.len() {
+        assert!(
+            PRODUCTIONS[i].num == i + 1,
+            "PRODUCTIONS must be numbered 1, 2, 3, ... in order"
+        );
+        i += 1;
+    }
+};
+
+// The production numbered `num`.
+pub fn production(num: usize) -> Option<&'static Production> {
+    num.checked_sub(1).and_then(|i| PRODUCTIONS.get(i))
+}
+
+// Shift/reduce conflicts settled in favour of the reduction, as (production,
+// lookahead). `stm ; stm ; stm` groups to the left this way.
+const PREFER_REDUCE: [(usize, &str); 1] = [(2, "Semicolon")];
+
+// The ACTION/GOTO table in the form the parser reads: "sN" shifts to state N,
+// "rN" reduces by production N, "gN" is a goto and "acc" accepts. `table_index`
+// gives the column of every terminal and nonterminal.
+pub struct ParseTable {
+    pub table: Vec<Vec<String>>,
+    pub table_index: HashMap<String, usize>,
+    // production number -> (left-hand side, length of the right-hand side)
+    pub prod_info: HashMap<usize, (String, usize)>,
+}
+
+// An LR(0) item: a production (0 is the augmented start rule) and how much of
+// its right-hand side has been seen.
+type Item = (usize, usize);
+
+struct Grammar {
+    // indexed by production number; 0 is `S' -> prog`
+    rules: Vec<(&'static str, &'static [&'static str])>,
+    nonterminals: HashSet<&'static str>,
+}
+
+impl Grammar {
+    fn new() -> Grammar {
+        // a production's number is its index here, since PRODUCTIONS starts at 1
+        let rules = std::iter::once(("S'", &NONTERMINALS[..1]))
+            .chain(PRODUCTIONS.iter().map(|prod| (prod.lhs, prod.rhs)))
+            .collect();
+        Grammar {
+            rules,
+            nonterminals: NONTERMINALS.iter().copied().collect(),
+        }
+    }
+
+    // The symbol after the dot, if the item isn't complete.
+    fn next_symbol(&self, (prod, dot): Item) -> Option<&'static str> {
+        self.rules[prod].1.get(dot).copied()
+    }
+
+    fn closure(&self, mut items: BTreeSet<Item>) -> BTreeSet<Item> {
+        let mut work: Vec<Item> = items.iter().copied().collect();
+        while let Some(item) = work.pop() {
+            let next = match self.next_symbol(item) {
+                Some(next) if self.nonterminals.contains(next) => next,
+                _ => continue,
+            };
+            for (prod, (lhs, _)) in self.rules.iter().enumerate().skip(1) {
+                if *lhs == next && items.insert((prod, 0)) {
+                    work.push((prod, 0));
+                }
+            }
+        }
+        items
+    }
+
+    fn goto(&self, items: &BTreeSet<Item>, symbol: &str) -> BTreeSet<Item> {
+        let moved = items
+            .iter()
+            .filter(|item| self.next_symbol(**item) == Some(symbol))
+            .map(|(prod, dot)| (*prod, dot + 1))
+            .collect();
+        self.closure(moved)
+    }
+
+    // FOLLOW of every nonterminal. No rule derives the empty string, so FIRST of
+    // a sequence is FIRST of its first symbol.
+    fn follow(&self) -> HashMap<&'static str, HashSet<&'static str>> {
+        let mut first: HashMap<&str, HashSet<&str>> = TERMINALS
+            .iter()
+            .chain([ERROR].iter())
+            .map(|t| (*t, HashSet::from([*t])))
+            .collect();
+        for nt in NONTERMINALS {
+            first.insert(nt, HashSet::new());
+        }
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (lhs, rhs) in self.rules.iter().skip(1) {
+                let add = first[rhs[0]].clone();
+                let set = first.get_mut(lhs).unwrap();
+                let before = set.len();
+                set.extend(add);
+                changed |= set.len() != before;
+            }
+        }
+
+        let mut follow: HashMap<&str, HashSet<&str>> = NONTERMINALS
+            .iter()
+            .map(|nt| (*nt, HashSet::new()))
+            .collect();
+        follow.get_mut(NONTERMINALS[0]).unwrap().insert("EndOfFile");
+        changed = true;
+        while changed {
+            changed = false;
+            for (lhs, rhs) in self.rules.iter().skip(1) {
+                for (i, symbol) in rhs.iter().enumerate() {
+                    if !self.nonterminals.contains(symbol) {
+                        continue;
+                    }
+                    let add = match rhs.get(i + 1) {
+                        Some(next) => first[next].clone(),
+                        None => follow[lhs].clone(),
+                    };
+                    let set = follow.get_mut(symbol).unwrap();
+                    let before = set.len();
+                    set.extend(add);
+                    changed |= set.len() != before;
+                }
+            }
+        }
+        follow
+    }
+
+    fn describe(&self, (prod, dot): Item) -> String {
+        let (lhs, rhs) = self.rules[prod];
+        let mut symbols: Vec<&str> = rhs.to_vec();
+        symbols.insert(dot, ".");
+        format!("{} -> {}", lhs, symbols.join(" "))
+    }
+}
+
+// Builds the SLR(1) table for PRODUCTIONS. Every conflict not settled by
+// PREFER_REDUCE is reported, with the items of the state it occurs in.
+pub fn build_table() -> Result<ParseTable, Vec<GrammarError>> {
+    let grammar = Grammar::new();
+    let follow = grammar.follow();
+    let columns: Vec<&str> = TERMINALS
+        .iter()
+        .chain([ERROR].iter())
+        .chain(NONTERMINALS.iter())
+        .copied()
+        .collect();
+    let table_index: HashMap<String, usize> = columns
+        .iter()
+        .enumerate()
+        .map(|(i, symbol)| (symbol.to_string(), i))
+        .collect();
+
+    // the canonical LR(0) collection, numbered in the order states are found
+    let mut states = vec![grammar.closure(BTreeSet::from([(0, 0)]))];
+    let mut transitions: Vec<Vec<(usize, usize)>> = Vec::new();
+    let mut i = 0;
+    while i < states.len() {
+        let mut edges = Vec::new();
+        for (column, symbol) in columns.iter().enumerate() {
+            let target = grammar.goto(&states[i], symbol);
+            if target.is_empty() {
+                continue;
+            }
+            let next = match states.iter().position(|state| *state == target) {
+                Some(next) => next,
+                None => {
+                    states.push(target);
+                    states.len() - 1
+                }
+            };
+            edges.push((column, next));
+        }
+        transitions.push(edges);
+        i += 1;
+    }
+
+    let mut table = vec![vec![String::new(); columns.len()]; states.len()];
+    let mut conflicts = Vec::new();
+    for (state, items) in states.iter().enumerate() {
+        for (column, next) in &transitions[state] {
+            let kind = if *column < TERMINALS.len() + 1 {
+                's'
+            } else {
+                'g'
+            };
+            table[state][*column] = format!("{}{}", kind, next);
+        }
+        for item in items {
+            let (prod, dot) = *item;
+            let (lhs, rhs) = grammar.rules[prod];
+            if dot < rhs.len() {
+                continue;
+            }
+            if prod == 0 {
+                table[state][table_index["EndOfFile"]] = String::from("acc");
+                continue;
+            }
+            let mut lookaheads: Vec<&str> = follow[lhs].iter().copied().collect();
+            lookaheads.sort_by_key(|t| table_index[*t]);
+            for lookahead in lookaheads {
+                let entry = &mut table[state][table_index[lookahead]];
+                let reduce = format!("r{}", prod);
+                let preferred =
+                    entry.starts_with('s') && PREFER_REDUCE.contains(&(prod, lookahead));
+                if entry.is_empty() || preferred {
+                    *entry = reduce;
+                } else {
+                    conflicts.push(GrammarError::Conflict {
+                        state,
+                        lookahead: lookahead.to_string(),
+                        actions: (entry.clone(), reduce),
+                        items: items.iter().map(|item| grammar.describe(*item)).collect(),
+                    });
+                }
+            }
+        }
+    }
+    if !conflicts.is_empty() {
+        return Err(conflicts);
+    }
+
+    let prod_info = PRODUCTIONS
+        .iter()
+        .map(|prod| (prod.num, (prod.lhs.to_string(), prod.rhs.len())))
+        .collect();
+    Ok(ParseTable {
+        table,
+        table_index,
+        prod_info,
+    })
+}
+
+// Reads a table written out by hand or by another generator, so the grammar can
+// be experimented with without a rebuild. The file is comma-separated; blank
+// lines and lines starting with `#` are skipped.
+//
+//     prod,<number>,<lhs>,<length>      one per production
+//     state,<symbol>,<symbol>,...       the column header
+//     <state>,<entry>,<entry>,...       one row per state, numbered from 0
+//
+// A nonterminal is a symbol on the left of some production; every other column
+// is a terminal. Productions keep the numbers their actions are found by, so each
+// one must have the left-hand side and length of the production with that number in
+// PRODUCTIONS. Every problem in the file is reported, not just the first.
+pub fn load_table(path: &str) -> Result<ParseTable, Vec<GrammarError>> {
+    let contents = fs::read_to_string(path).map_err(|why| {
+        vec![GrammarError::InvalidTable(
+            path.to_string(),
+            format!("couldn't read the table: {}", why),
+        )]
+    })?;
+    let mut errors = Vec::new();
+    let mut error = |line: usize, message: String| {
+        errors.push(GrammarError::InvalidTable(
+            format!("{}:{}", path, line),
+            message,
+        ))
+    };
+
+    let mut prod_info: HashMap<usize, (String, usize)> = HashMap::new();
+    let mut prod_lines: HashMap<usize, usize> = HashMap::new();
+    let mut columns: Option<(usize, Vec<String>)> = None;
+    // each row with the line it came from
+    let mut rows: Vec<(usize, Vec<String>)> = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line_num = i + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<String> = line.split(',').map(|f| f.trim().to_string()).collect();
+        match fields[0].as_str() {
+            "prod" => {
+                let (num, lhs, len) = match fields.as_slice() {
+                    [_, num, lhs, len] => (num, lhs, len),
+                    _ => {
+                        error(
+                            line_num,
+                            String::from("expected prod,<number>,<lhs>,<length>"),
+                        );
+                        continue;
+                    }
+                };
+                match (num.parse::<usize>(), len.parse::<usize>()) {
+                    (Ok(num), Ok(len)) if num > 0 && len > 0 => {
+                        if prod_info.insert(num, (lhs.clone(), len)).is_some() {
+                            error(line_num, format!("production {} is defined twice", num));
+                        }
+                        prod_lines.insert(num, line_num);
+                    }
+                    _ => error(
+                        line_num,
+                        String::from("production number and length must be positive integers"),
+                    ),
+                }
+            }
+            "state" => {
+                if columns.is_some() {
+                    error(line_num, String::from("the column header appears twice"));
+                    continue;
+                }
+                columns = Some((line_num, fields[1..].to_vec()));
+            }
+            _ if columns.is_none() => {
+                error(line_num, String::from("state row before the column header"));
+            }
+            _ => rows.push((line_num, fields)),
+        }
+    }
+    let (header_line, columns) = match columns {
+        Some(columns) => columns,
+        None => {
+            errors.push(GrammarError::InvalidTable(
+                path.to_string(),
+                String::from("no column header (a line starting with state,)"),
+            ));
+            return Err(errors);
+        }
+    };
+
+    let mut table_index = HashMap::new();
+    for (i, symbol) in columns.iter().enumerate() {
+        if table_index.insert(symbol.clone(), i).is_some() {
+            error(header_line, format!("column {} appears twice", symbol));
+        }
+    }
+    let nonterminals: HashSet<&str> = prod_info.values().map(|(lhs, _)| lhs.as_str()).collect();
+    for terminal in TERMINALS {
+        if nonterminals.contains(terminal) {
+            error(
+                header_line,
+                format!("token {} is used as a nonterminal", terminal),
+            );
+        } else if !table_index.contains_key(terminal) {
+            error(header_line, format!("no column for token {}", terminal));
+        }
+    }
+    let mut lhs_names: Vec<&&str> = nonterminals.iter().collect();
+    lhs_names.sort();
+    for lhs in lhs_names {
+        if !table_index.contains_key(*lhs) {
+            error(header_line, format!("no column for nonterminal {}", lhs));
+        }
+    }
+    let mut nums: Vec<&usize> = prod_info.keys().collect();
+    nums.sort();
+    for num in nums {
+        let (lhs, len) = &prod_info[num];
+        match production(*num) {
+            Some(prod) if prod.lhs == lhs && prod.rhs.len() == *len => {}
+            Some(prod) => error(
+                prod_lines[num],
+                format!(
+                    "production {} is {} with {} symbols, but the grammar has it as {} with {}",
+                    num,
+                    lhs,
+                    len,
+                    prod.lhs,
+                    prod.rhs.len()
+                ),
+            ),
+            None => error(
+                prod_lines[num],
+                format!("production {} is not in the grammar", num),
+            ),
+        }
+    }
+
+    let mut table = Vec::new();
+    let mut accepts = false;
+    for (state, (line_num, fields)) in rows.iter().enumerate() {
+        if fields[0] != state.to_string() {
+            error(
+                *line_num,
+                format!("expected the row for state {}, found {}", state, fields[0]),
+            );
+        }
+        if fields.len() != columns.len() + 1 {
+            error(
+                *line_num,
+                format!(
+                    "row has {} entries but the header has {} columns",
+                    fields.len() - 1,
+                    columns.len()
+                ),
+            );
+            continue;
+        }
+        for (symbol, entry) in columns.iter().zip(&fields[1..]) {
+            let terminal = !nonterminals.contains(symbol.as_str());
+            let target = entry.get(1..).and_then(|n| n.parse::<usize>().ok());
+            let valid = match (entry.chars().next(), target) {
+                _ if entry.is_empty() => true,
+                _ if entry == "acc" => {
+                    accepts = true;
+                    terminal
+                }
+                (Some('s'), Some(next)) => {
+                    if next >= rows.len() {
+                        error(*line_num, format!("{} on {} names no state", entry, symbol));
+                    }
+                    terminal
+                }
+                (Some('g'), Some(next)) => {
+                    if next >= rows.len() {
+                        error(*line_num, format!("{} on {} names no state", entry, symbol));
+                    }
+                    !terminal
+                }
+                (Some('r'), Some(prod)) => {
+                    if !prod_info.contains_key(&prod) {
+                        error(
+                            *line_num,
+                            format!("{} on {} names no production", entry, symbol),
+                        );
+                    }
+                    terminal
+                }
+                _ => false,
+            };
+            if !valid {
+                error(
+                    *line_num,
+                    format!("{} is not a valid entry for {}", entry, symbol),
+                );
+            }
+        }
+        table.push(fields[1..].to_vec());
+    }
+    if table.is_empty() {
+        error(header_line, String::from("the table has no states"));
+    } else if !accepts {
+        error(header_line, String::from("no state accepts"));
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(ParseTable {
+        table,
+        table_index,
+        prod_info,
+    })
+}
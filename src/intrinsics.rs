@@ -0,0 +1,61 @@
+// Built-in functions, called as `name(args)` anywhere an expression can go.
+// CodeGenerator lowers each one inline; there are no user functions to shadow them.
+const INTRINSICS: [(&str, usize); 5] =
+    [("abs", 1), ("min", 2), ("max", 2), ("pow", 2), ("sqrt", 1)];
+
+pub fn arity(name: &str) -> Option<usize> {
+    INTRINSICS
+        .iter()
+        .find(|(intrinsic, _)| *intrinsic == name)
+        .map(|(_, arity)| *arity)
+}
+
+pub fn names() -> Vec<&'static str> {
+    INTRINSICS.iter().map(|(name, _)| *name).collect()
+}
+
+// Evaluates an intrinsic on constant arguments. This has to agree with the code
+// CodeGenerator emits for it, wrapping included.
+pub fn fold(name: &str, args: &[i32]) -> Option<i32> {
+    match (name, args) {
+        ("abs", [x]) => Some(x.wrapping_abs()),
+        ("min", [a, b]) => Some(*a.min(b)),
+        ("max", [a, b]) => Some(*a.max(b)),
+        ("pow", [base, exp]) => Some(pow(*base, *exp)),
+        ("sqrt", [x]) => Some(sqrt(*x)),
+        _ => None,
+    }
+}
+
+// Square-and-multiply. A negative exponent gives 0 for every base, 1 and -1
+// included.
+fn pow(base: i32, exp: i32) -> i32 {
+    if exp < 0 {
+        return 0;
+    }
+    let (mut result, mut base, mut exp) = (1i32, base, exp);
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = result.wrapping_mul(base);
+        }
+        base = base.wrapping_mul(base);
+        exp >>= 1;
+    }
+    result
+}
+
+// Newton's method on integers, starting from (x + 1) / 2: the estimate falls
+// until it reaches floor(sqrt(x)). Negative values give 0.
+fn sqrt(x: i32) -> i32 {
+    if x <= 0 {
+        return 0;
+    }
+    let x = x as u32;
+    let mut root = x;
+    let mut next = (x + 1) >> 1;
+    while next < root {
+        root = next;
+        next = (next + x / next) >> 1;
+    }
+    root as i32
+}
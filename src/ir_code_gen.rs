@@ -1,148 +1,658 @@
-use crate::ast::{EseqExp, Exp};
+use std::fmt;
 
-#[derive(Debug)]
+use crate::ast::{EseqExp, Exp, NodeId};
+use crate::errors::ParseError;
+use crate::grammar::{production, Production, NONTERMINALS};
+use crate::lex::Span;
+
+// The first symbol of the production being reduced, the one part of a CST node
+// gen_line reads directly. A terminal's Value is its token text; a
+// Nonterminal's is the symbol's name. Every Nonterminal has exactly one
+// SemanticValue on the semantic stack.
 pub enum StackItem {
-    State(usize),
-    Value(String),
+    Value(String, Span),
+    Nonterminal(String, Span),
 }
 
 use crate::ast::{
-    AssignStm, CompExp, CompoundStm, ExpList, ForStm, IdExp, LastExpList, NumExp, OpExp,
-    PairExpList, PrintStm, Stm,
+    AsmOperand, AsmStm, AssignStm, CallExp, CompExp, CompoundStm, ConstStm, ErrorStm, ExpList,
+    ExportStm, ForStm, IdExp, ImportStm, LastExpList, NodeIds, NumExp, OpExp, PairExpList,
+    PrintStm, Stm, StrExp,
 };
 
-pub fn gen_line(
-    prod_num: usize,
-    icg_stack: &mut Vec<Box<dyn std::any::Any>>,
-    stack_item: StackItem,
-) {
-    match prod_num {
-        2 => {
-            let t1 = icg_stack.pop().unwrap().downcast::<Stm>().unwrap();
-            let t2 = icg_stack.pop().unwrap().downcast::<Stm>().unwrap();
-            let compound_stm = CompoundStm { stm1: t2, stm2: t1 };
-            icg_stack.push(Box::new(Stm::Compound(compound_stm)));
-        }
-        4 => {
-            let t1 = icg_stack.pop().unwrap().downcast::<ExpList>().unwrap();
-            let print_stm = PrintStm { exps: t1 };
-            icg_stack.push(Box::new(Stm::Print(print_stm)));
+// What a nonterminal reduces to. Terminals put nothing on the semantic stack;
+// their text reaches gen_line through the StackItem of a production's first
+// symbol, or through a Token value from `name` and `text`.
+pub enum SemanticValue {
+    Stm(Box<Stm>),
+    Exp(Box<Exp>),
+    ExpList(Box<ExpList>),
+    BinOp(String),
+    Token(String, Span),
+    AsmOperand(AsmOperand),
+    AsmOperands(Vec<AsmOperand>),
+}
+
+impl SemanticValue {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SemanticValue::Stm(_) => "Stm",
+            SemanticValue::Exp(_) => "Exp",
+            SemanticValue::ExpList(_) => "ExpList",
+            SemanticValue::BinOp(_) => "BinOp",
+            SemanticValue::Token(..) => "Token",
+            SemanticValue::AsmOperand(_) => "AsmOperand",
+            SemanticValue::AsmOperands(_) => "AsmOperands",
         }
-        5 => {
-            let t1 = icg_stack.pop().unwrap().downcast::<Stm>().unwrap();
-            let t2 = icg_stack.pop().unwrap().downcast::<Stm>().unwrap();
-            let t3 = icg_stack.pop().unwrap().downcast::<Exp>().unwrap();
-            let t4 = icg_stack.pop().unwrap().downcast::<Stm>().unwrap();
-            let for_stm = ForStm {
-                iter: t4,
-                comp: t3,
-                inc: t2,
-                body: t1,
-            };
-            icg_stack.push(Box::new(Stm::For(for_stm)));
+    }
+
+    // The AST node this value is, if it is one.
+    pub fn node_id(&self) -> Option<NodeId> {
+        match self {
+            SemanticValue::Stm(stm) => Some(stm.node_id()),
+            SemanticValue::Exp(exp) => Some(exp.node_id()),
+            SemanticValue::ExpList(exps) => Some(exps.node_id()),
+            _ => None,
         }
-        6 => {
-            if let StackItem::Value(value) = stack_item {
-                let t2 = icg_stack.pop().unwrap().downcast::<Exp>().unwrap();
-                let assign_stm = AssignStm {
-                    id: Box::new(Exp::Id(IdExp { id: value })),
-                    exp: t2,
-                };
-                icg_stack.push(Box::new(Stm::Assign(assign_stm)));
-            } else {
-                panic!("Expected StackItem::Value, but got something else");
+    }
+}
+
+impl fmt::Display for SemanticValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SemanticValue::Stm(stm) => write!(f, "{}", stm),
+            SemanticValue::Exp(exp) => write!(f, "{}", exp),
+            SemanticValue::ExpList(exps) => write!(f, "{}", exps),
+            SemanticValue::BinOp(text) | SemanticValue::Token(text, _) => write!(f, "{}", text),
+            SemanticValue::AsmOperand(operand) => write!(f, "{}", operand),
+            SemanticValue::AsmOperands(operands) => {
+                let operands: Vec<String> = operands.iter().map(|op| op.to_string()).collect();
+                write!(f, "{}", operands.join(", "))
             }
         }
-        7 => {
-            let t1 = icg_stack.pop().unwrap().downcast::<ExpList>().unwrap();
-            let t2 = icg_stack.pop().unwrap().downcast::<Exp>().unwrap();
-            let pair_exp_list = PairExpList { head: t2, tail: t1 };
-            icg_stack.push(Box::new(ExpList::Pair(pair_exp_list)));
-        }
-        8 => {
-            let t1 = icg_stack.pop().unwrap().downcast::<Exp>().unwrap();
-            let last_exp_list = LastExpList { head: t1 };
-            icg_stack.push(Box::new(ExpList::Last(last_exp_list)));
-        }
-        9 => {
-            if let StackItem::Value(value) = stack_item {
-                let id_exp = IdExp { id: value };
-                icg_stack.push(Box::new(Exp::Id(id_exp)));
-            } else {
-                panic!("Expected StackItem::Value, but got something else");
-            }
+    }
+}
+
+// What an action reads while reducing by production `prod_num`: the values of
+// its right-hand side, popped last first, the text and span of its first symbol,
+// and its extent, from its first token to its last, which is the span of the
+// node built. Every node gets a NodeId from `ids`. A value of the wrong type
+// means the parse table doesn't match the actions, which is reported against
+// the production instead of panicking.
+pub struct Values<'a> {
+    stack: &'a mut Vec<SemanticValue>,
+    prod_num: usize,
+    value: String,
+    span: Span,
+    extent: &'a Span,
+    ids: &'a mut NodeIds,
+}
+
+impl<'a> Values<'a> {
+    fn mismatch(&self, expected: &str, found: Option<SemanticValue>) -> ParseError {
+        let found = found.as_ref().map_or("nothing", SemanticValue::kind);
+        ParseError::InternalGrammar(
+            self.prod_num,
+            format!(
+                "expected {} on the semantic stack, found {}",
+                expected, found
+            ),
+            self.span.clone(),
+        )
+    }
+
+    fn stm(&mut self) -> Result<Box<Stm>, ParseError> {
+        match self.stack.pop() {
+            Some(SemanticValue::Stm(stm)) => Ok(stm),
+            other => Err(self.mismatch("Stm", other)),
         }
-        10 => {
-            if let StackItem::Value(value) = stack_item {
-                let num_exp = NumExp { num: value };
-                icg_stack.push(Box::new(Exp::Num(num_exp)));
-            } else {
-                panic!("Expected StackItem::Value, but got something else");
-            }
+    }
+
+    fn exp(&mut self) -> Result<Box<Exp>, ParseError> {
+        match self.stack.pop() {
+            Some(SemanticValue::Exp(exp)) => Ok(exp),
+            other => Err(self.mismatch("Exp", other)),
         }
-        13 => {
-            let t1 = icg_stack.pop().unwrap().downcast::<Exp>().unwrap();
-            let t2 = icg_stack.pop().unwrap().downcast::<Exp>().unwrap();
-            let comp_exp = CompExp {
-                left: t2,
-                right: t1,
-                op: 0,
-            };
-            icg_stack.push(Box::new(Exp::Comp(comp_exp)));
+    }
+
+    fn exp_list(&mut self) -> Result<Box<ExpList>, ParseError> {
+        match self.stack.pop() {
+            Some(SemanticValue::ExpList(exps)) => Ok(exps),
+            other => Err(self.mismatch("ExpList", other)),
         }
-        14 => {
-            let t1 = icg_stack.pop().unwrap().downcast::<Exp>().unwrap();
-            let t2 = icg_stack.pop().unwrap().downcast::<Exp>().unwrap();
-            let comp_exp = CompExp {
-                left: t2,
-                right: t1,
-                op: 1,
-            };
-            icg_stack.push(Box::new(Exp::Comp(comp_exp)));
+    }
+
+    fn bin_op(&mut self) -> Result<String, ParseError> {
+        match self.stack.pop() {
+            Some(SemanticValue::BinOp(op)) => Ok(op),
+            other => Err(self.mismatch("BinOp", other)),
         }
-        15 => {
-            if let StackItem::Value(value) = stack_item {
-                let t1 = icg_stack.pop().unwrap().downcast::<Exp>().unwrap();
-                let t2 = icg_stack.pop().unwrap().downcast::<String>().unwrap();
-
-                let op_exp = OpExp {
-                    left: Box::new(Exp::Id(IdExp { id: value })),
-                    right: t1,
-                    op: *t2,
-                };
-                icg_stack.push(Box::new(Exp::Op(op_exp)));
-            }
+    }
+
+    fn token(&mut self) -> Result<(String, Span), ParseError> {
+        match self.stack.pop() {
+            Some(SemanticValue::Token(value, span)) => Ok((value, span)),
+            other => Err(self.mismatch("Token", other)),
         }
-        16 => {
-            if let StackItem::Value(value) = stack_item {
-                let t1 = icg_stack.pop().unwrap().downcast::<Exp>().unwrap();
-                let t2 = icg_stack.pop().unwrap().downcast::<String>().unwrap();
-                let op_exp = OpExp {
-                    left: Box::new(Exp::Num(NumExp { num: value })),
-                    right: t1,
-                    op: *t2,
-                };
-                icg_stack.push(Box::new(Exp::Op(op_exp)));
-            }
+    }
+
+    fn asm_operand(&mut self) -> Result<AsmOperand, ParseError> {
+        match self.stack.pop() {
+            Some(SemanticValue::AsmOperand(operand)) => Ok(operand),
+            other => Err(self.mismatch("AsmOperand", other)),
         }
-        17 => {
-            let t1 = icg_stack.pop().unwrap().downcast::<Exp>().unwrap();
-            let t2 = icg_stack.pop().unwrap().downcast::<Stm>().unwrap();
-            let eseq = EseqExp { stm: t2, exp: t1 };
-            icg_stack.push(Box::new(Exp::Eseq(eseq)));
+    }
+
+    fn asm_operands(&mut self) -> Result<Vec<AsmOperand>, ParseError> {
+        match self.stack.pop() {
+            Some(SemanticValue::AsmOperands(operands)) => Ok(operands),
+            other => Err(self.mismatch("AsmOperands", other)),
         }
-        18 => {
-            icg_stack.push(Box::new(String::from("add")));
+    }
+
+    // The text of the production's first symbol, a token.
+    fn text(&mut self) -> String {
+        std::mem::take(&mut self.value)
+    }
+
+    fn id_exp(&mut self) -> Box<Exp> {
+        Box::new(Exp::Id(IdExp {
+            id: self.text(),
+            span: self.span.clone(),
+            node_id: self.ids.fresh(),
+        }))
+    }
+
+    fn num_exp(&mut self) -> Box<Exp> {
+        Box::new(Exp::Num(NumExp {
+            num: self.text(),
+            span: self.span.clone(),
+            node_id: self.ids.fresh(),
+        }))
+    }
+
+    fn str_exp(&mut self) -> Box<Exp> {
+        Box::new(Exp::Str(StrExp {
+            value: self.text(),
+            span: self.span.clone(),
+            node_id: self.ids.fresh(),
+        }))
+    }
+}
+
+// Builds the AST value of one production from the values of its right-hand
+// side. Each production in grammar::PRODUCTIONS that builds something names its
+// action.
+pub type Action = fn(&mut Values) -> Result<SemanticValue, ParseError>;
+
+// Runs the action of production `prod_num` for a reduction and pushes the AST
+// node it builds on `icg_stack`, in place of the values of its right-hand side.
+// `stack_item` is the production's first symbol and `extent` the span of the
+// node built.
+pub fn gen_line(
+    prod_num: usize,
+    icg_stack: &mut Vec<SemanticValue>,
+    stack_item: StackItem,
+    extent: &Span,
+    ids: &mut NodeIds,
+) -> Result<(), ParseError> {
+    let prod = match production(prod_num) {
+        Some(prod) => prod,
+        None => {
+            let span = match stack_item {
+                StackItem::Value(_, span) | StackItem::Nonterminal(_, span) => span,
+            };
+            return Err(ParseError::InternalGrammar(
+                prod_num,
+                String::from("there is no such production"),
+                span,
+            ));
         }
-        19 => {
-            icg_stack.push(Box::new(String::from("sub")));
+    };
+    let (value, span) = first_symbol(prod, stack_item)?;
+    let action = match prod.action {
+        Some(action) => action,
+        None => return Ok(()),
+    };
+    let mut values = Values {
+        stack: icg_stack,
+        prod_num,
+        value,
+        span,
+        extent,
+        ids,
+    };
+    let result = action(&mut values)?;
+    icg_stack.push(result);
+    Ok(())
+}
+
+// The text and span of a production's first symbol, which must be the token or
+// nonterminal the production starts with. A table that reduced something else
+// doesn't match the grammar.
+fn first_symbol(prod: &Production, stack_item: StackItem) -> Result<(String, Span), ParseError> {
+    let expected = prod.rhs[0];
+    let nonterminal = NONTERMINALS.contains(&expected);
+    let (found, span) = match stack_item {
+        StackItem::Value(value, span) if !nonterminal => return Ok((value, span)),
+        StackItem::Nonterminal(name, span) if nonterminal && name == expected => {
+            return Ok((name, span))
         }
-        20 => {
-            icg_stack.push(Box::new(String::from("mul")));
+        StackItem::Value(value, span) => (format!("the token `{}`", value), span),
+        StackItem::Nonterminal(name, span) => (name, span),
+    };
+    Err(ParseError::InternalGrammar(
+        prod.num,
+        format!("expected {} first, found {}", expected, found),
+        span,
+    ))
+}
+
+// stm -> stm ; stm
+pub fn compound_stm(v: &mut Values) -> Result<SemanticValue, ParseError> {
+    let stm2 = v.stm()?;
+    let stm1 = v.stm()?;
+    Ok(stm(Stm::Compound(CompoundStm {
+        stm1,
+        stm2,
+        span: v.extent.clone(),
+        node_id: v.ids.fresh(),
+    })))
+}
+
+// stm -> print ( explist )
+pub fn print_stm(v: &mut Values) -> Result<SemanticValue, ParseError> {
+    print(v, true, false)
+}
+
+// stm -> write ( explist )
+pub fn write_stm(v: &mut Values) -> Result<SemanticValue, ParseError> {
+    print(v, false, false)
+}
+
+// stm -> print_char ( explist )
+pub fn print_char_stm(v: &mut Values) -> Result<SemanticValue, ParseError> {
+    print(v, false, true)
+}
+
+fn print(v: &mut Values, newline: bool, chars: bool) -> Result<SemanticValue, ParseError> {
+    let exps = v.exp_list()?;
+    Ok(stm(Stm::Print(PrintStm {
+        exps,
+        newline,
+        chars,
+        span: v.extent.clone(),
+        node_id: v.ids.fresh(),
+    })))
+}
+
+// stm -> for ( assignstm ; compexp ; assignstm ) { prog }
+pub fn for_stm(v: &mut Values) -> Result<SemanticValue, ParseError> {
+    let body = v.stm()?;
+    let inc = v.stm()?;
+    let comp = v.exp()?;
+    let iter = v.stm()?;
+    Ok(stm(Stm::For(ForStm {
+        iter,
+        comp,
+        inc,
+        body,
+        span: v.extent.clone(),
+        node_id: v.ids.fresh(),
+    })))
+}
+
+// assignstm -> ID := exp
+pub fn assign_stm(v: &mut Values) -> Result<SemanticValue, ParseError> {
+    let exp = v.exp()?;
+    let id = v.id_exp();
+    Ok(stm(Stm::Assign(AssignStm {
+        id,
+        exp,
+        i64_decl: false,
+        span: v.extent.clone(),
+        node_id: v.ids.fresh(),
+    })))
+}
+
+// explist -> exp , explist
+pub fn pair_exp_list(v: &mut Values) -> Result<SemanticValue, ParseError> {
+    let tail = v.exp_list()?;
+    let head = v.exp()?;
+    Ok(pair(v, head, tail))
+}
+
+// explist -> Str , explist
+pub fn str_pair_exp_list(v: &mut Values) -> Result<SemanticValue, ParseError> {
+    let tail = v.exp_list()?;
+    let head = v.str_exp();
+    Ok(pair(v, head, tail))
+}
+
+fn pair(v: &mut Values, head: Box<Exp>, tail: Box<ExpList>) -> SemanticValue {
+    SemanticValue::ExpList(Box::new(ExpList::Pair(PairExpList {
+        head,
+        tail,
+        span: v.extent.clone(),
+        node_id: v.ids.fresh(),
+    })))
+}
+
+// explist -> exp
+pub fn last_exp_list(v: &mut Values) -> Result<SemanticValue, ParseError> {
+    let head = v.exp()?;
+    Ok(last(v, head))
+}
+
+// explist -> Str
+pub fn str_last_exp_list(v: &mut Values) -> Result<SemanticValue, ParseError> {
+    let head = v.str_exp();
+    Ok(last(v, head))
+}
+
+fn last(v: &mut Values, head: Box<Exp>) -> SemanticValue {
+    SemanticValue::ExpList(Box::new(ExpList::Last(LastExpList {
+        head,
+        span: v.extent.clone(),
+        node_id: v.ids.fresh(),
+    })))
+}
+
+// exp -> ID
+pub fn id_exp(v: &mut Values) -> Result<SemanticValue, ParseError> {
+    Ok(SemanticValue::Exp(v.id_exp()))
+}
+
+// exp -> Num and exp -> Char; character literals arrive as their code point and
+// are plain numbers from here on
+pub fn num_exp(v: &mut Values) -> Result<SemanticValue, ParseError> {
+    Ok(SemanticValue::Exp(v.num_exp()))
+}
+
+// compexp -> exp < exp
+pub fn less_than(v: &mut Values) -> Result<SemanticValue, ParseError> {
+    comp(v, 0)
+}
+
+// compexp -> exp > exp
+pub fn greater_than(v: &mut Values) -> Result<SemanticValue, ParseError> {
+    comp(v, 1)
+}
+
+fn comp(v: &mut Values, op: u8) -> Result<SemanticValue, ParseError> {
+    let right = v.exp()?;
+    let left = v.exp()?;
+    Ok(exp(Exp::Comp(CompExp {
+        left,
+        right,
+        op,
+        span: v.extent.clone(),
+        node_id: v.ids.fresh(),
+    })))
+}
+
+// opexp -> ID binop exp
+pub fn id_op_exp(v: &mut Values) -> Result<SemanticValue, ParseError> {
+    let right = v.exp()?;
+    let op = v.bin_op()?;
+    let left = v.id_exp();
+    Ok(op_exp(v, left, op, right))
+}
+
+// opexp -> Num binop exp and opexp -> Char binop exp
+pub fn num_op_exp(v: &mut Values) -> Result<SemanticValue, ParseError> {
+    let right = v.exp()?;
+    let op = v.bin_op()?;
+    let left = v.num_exp();
+    Ok(op_exp(v, left, op, right))
+}
+
+// opexp -> call binop exp
+pub fn call_op_exp(v: &mut Values) -> Result<SemanticValue, ParseError> {
+    let right = v.exp()?;
+    let op = v.bin_op()?;
+    let left = v.exp()?;
+    Ok(op_exp(v, left, op, right))
+}
+
+fn op_exp(v: &mut Values, left: Box<Exp>, op: String, right: Box<Exp>) -> SemanticValue {
+    exp(Exp::Op(OpExp {
+        left,
+        right,
+        op,
+        span: v.extent.clone(),
+        node_id: v.ids.fresh(),
+    }))
+}
+
+// eseqexp -> ( stm , exp )
+pub fn eseq_exp(v: &mut Values) -> Result<SemanticValue, ParseError> {
+    let value = v.exp()?;
+    let stm = v.stm()?;
+    Ok(exp(Exp::Eseq(EseqExp {
+        stm,
+        exp: value,
+        span: v.extent.clone(),
+        node_id: v.ids.fresh(),
+    })))
+}
+
+// binop -> + and the other operators
+pub fn add(_: &mut Values) -> Result<SemanticValue, ParseError> {
+    Ok(SemanticValue::BinOp(String::from("add")))
+}
+
+pub fn sub(_: &mut Values) -> Result<SemanticValue, ParseError> {
+    Ok(SemanticValue::BinOp(String::from("sub")))
+}
+
+pub fn mul(_: &mut Values) -> Result<SemanticValue, ParseError> {
+    Ok(SemanticValue::BinOp(String::from("mul")))
+}
+
+pub fn div(_: &mut Values) -> Result<SemanticValue, ParseError> {
+    Ok(SemanticValue::BinOp(String::from("div")))
+}
+
+// stm -> i64 assignstm; the declaration starts at `i64`
+pub fn i64_decl(v: &mut Values) -> Result<SemanticValue, ParseError> {
+    let mut stm = v.stm()?;
+    if let Stm::Assign(assign_stm) = stm.as_mut() {
+        assign_stm.i64_decl = true;
+        assign_stm.span = v.extent.clone();
+    }
+    Ok(SemanticValue::Stm(stm))
+}
+
+// stm -> import name
+pub fn import_stm(v: &mut Values) -> Result<SemanticValue, ParseError> {
+    let (module, _) = v.token()?;
+    Ok(stm(Stm::Import(ImportStm {
+        module,
+        span: v.extent.clone(),
+        node_id: v.ids.fresh(),
+    })))
+}
+
+// stm -> export name
+pub fn export_stm(v: &mut Values) -> Result<SemanticValue, ParseError> {
+    let (id, _) = v.token()?;
+    Ok(stm(Stm::Export(ExportStm {
+        id,
+        span: v.extent.clone(),
+        node_id: v.ids.fresh(),
+    })))
+}
+
+// name -> ID and text -> Str: a bare name or string, as used by import, export
+// and asm
+pub fn token(v: &mut Values) -> Result<SemanticValue, ParseError> {
+    Ok(SemanticValue::Token(v.text(), v.span.clone()))
+}
+
+// stm -> asm ( text asmops )
+pub fn asm_stm(v: &mut Values) -> Result<SemanticValue, ParseError> {
+    let operands = v.asm_operands()?;
+    asm(v, operands)
+}
+
+// stm -> asm ( text )
+pub fn bare_asm_stm(v: &mut Values) -> Result<SemanticValue, ParseError> {
+    asm(v, Vec::new())
+}
+
+fn asm(v: &mut Values, operands: Vec<AsmOperand>) -> Result<SemanticValue, ParseError> {
+    let (template, _) = v.token()?;
+    Ok(stm(Stm::Asm(AsmStm {
+        template,
+        operands,
+        span: v.extent.clone(),
+        node_id: v.ids.fresh(),
+    })))
+}
+
+// asmops -> , asmop asmops
+pub fn asm_operands(v: &mut Values) -> Result<SemanticValue, ParseError> {
+    let mut operands = v.asm_operands()?;
+    let operand = v.asm_operand()?;
+    operands.insert(0, operand);
+    Ok(SemanticValue::AsmOperands(operands))
+}
+
+// asmops -> , asmop
+pub fn last_asm_operand(v: &mut Values) -> Result<SemanticValue, ParseError> {
+    let operand = v.asm_operand()?;
+    Ok(SemanticValue::AsmOperands(vec![operand]))
+}
+
+// asmop -> out ( name )
+pub fn out_operand(v: &mut Values) -> Result<SemanticValue, ParseError> {
+    let (id, span) = v.token()?;
+    let id_exp = IdExp {
+        id,
+        span,
+        node_id: v.ids.fresh(),
+    };
+    Ok(SemanticValue::AsmOperand(AsmOperand::Out(Box::new(
+        Exp::Id(id_exp),
+    ))))
+}
+
+// asmop -> in ( exp )
+pub fn in_operand(v: &mut Values) -> Result<SemanticValue, ParseError> {
+    let exp = v.exp()?;
+    Ok(SemanticValue::AsmOperand(AsmOperand::In(exp)))
+}
+
+// asmop -> clobber ( text )
+pub fn clobber_operand(v: &mut Values) -> Result<SemanticValue, ParseError> {
+    let (reg, _) = v.token()?;
+    Ok(SemanticValue::AsmOperand(AsmOperand::Clobber(reg)))
+}
+
+// call -> ID ( explist )
+pub fn call_exp(v: &mut Values) -> Result<SemanticValue, ParseError> {
+    let args = v.exp_list()?;
+    Ok(exp(Exp::Call(CallExp {
+        name: v.text(),
+        args,
+        span: v.extent.clone(),
+        node_id: v.ids.fresh(),
+    })))
+}
+
+// stm -> const name := exp
+pub fn const_stm(v: &mut Values) -> Result<SemanticValue, ParseError> {
+    let exp = v.exp()?;
+    let (id, _) = v.token()?;
+    Ok(stm(Stm::Const(ConstStm {
+        id,
+        exp,
+        span: v.extent.clone(),
+        node_id: v.ids.fresh(),
+    })))
+}
+
+// stm -> error, standing in for a statement with a syntax error
+pub fn error_stm(v: &mut Values) -> Result<SemanticValue, ParseError> {
+    Ok(stm(Stm::Error(ErrorStm {
+        span: v.span.clone(),
+        node_id: v.ids.fresh(),
+    })))
+}
+
+fn stm(stm: Stm) -> SemanticValue {
+    SemanticValue::Stm(Box::new(stm))
+}
+
+fn exp(exp: Exp) -> SemanticValue {
+    SemanticValue::Exp(Box::new(exp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{gen_line, SemanticValue, StackItem};
+    use crate::ast::{Exp, NodeIds, NumExp};
+    use crate::errors::ParseError;
+    use crate::lex::Span;
+
+    fn reduce(prod_num: usize, first: StackItem) -> Result<Vec<SemanticValue>, ParseError> {
+        let mut ids = NodeIds::default();
+        let mut stack = vec![SemanticValue::Exp(Box::new(Exp::Num(NumExp {
+            num: String::from("1"),
+            span: Span::default(),
+            node_id: ids.fresh(),
+        })))];
+        gen_line(prod_num, &mut stack, first, &Span::default(), &mut ids)?;
+        Ok(stack)
+    }
+
+    fn value(result: Result<Vec<SemanticValue>, ParseError>) -> String {
+        match result {
+            Ok(stack) => stack[0].to_string(),
+            Err(err) => panic!("unexpected error {}", err),
         }
-        21 => {
-            icg_stack.push(Box::new(String::from("div")));
+    }
+
+    fn internal_error(result: Result<Vec<SemanticValue>, ParseError>) -> String {
+        match result {
+            Err(ParseError::InternalGrammar(_, why, _)) => why,
+            Err(err) => panic!("unexpected error {}", err),
+            Ok(_) => panic!("the reduction succeeded"),
         }
-        _ => {}
+    }
+
+    #[test]
+    fn first_symbol_of_the_expected_kind() {
+        // assignstm -> ID := exp
+        let first = StackItem::Value(String::from("x"), Span::default());
+        assert_eq!(value(reduce(6, first)), "x := 1");
+        // exp -> opexp only passes its value up
+        let first = StackItem::Nonterminal(String::from("opexp"), Span::default());
+        assert_eq!(value(reduce(11, first)), "1");
+    }
+
+    #[test]
+    fn first_symbol_of_the_wrong_kind_is_an_internal_error() {
+        let found = StackItem::Nonterminal(String::from("exp"), Span::default());
+        assert_eq!(
+            internal_error(reduce(6, found)),
+            "expected ID first, found exp"
+        );
+        let found = StackItem::Value(String::from("x"), Span::default());
+        assert_eq!(
+            internal_error(reduce(11, found)),
+            "expected opexp first, found the token `x`"
+        );
+        let found = StackItem::Nonterminal(String::from("eseqexp"), Span::default());
+        assert_eq!(
+            internal_error(reduce(11, found)),
+            "expected opexp first, found eseqexp"
+        );
+        let found = StackItem::Value(String::from("x"), Span::default());
+        assert_eq!(
+            internal_error(reduce(45, found)),
+            "there is no such production"
+        );
     }
 }
@@ -1,25 +1,72 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, BufRead};
+
 use crate::errors::TokenError;
+use crate::preprocess::SourceMap;
+
+// Where a token sits in the lexed text. `start` and `end` are byte offsets into
+// the preprocessed source, and `line` is a line of it; SourceMap turns that into
+// the original file and line. `file` indexes the SourceMap's files and `col` counts
+// characters from 1.
+#[derive(Clone, Default, PartialEq)]
+pub struct Span {
+    pub file: usize,
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Span {
+    // From the start of `self` to the end of `other`.
+    pub fn to(&self, other: &Span) -> Span {
+        Span {
+            end: other.end,
+            ..self.clone()
+        }
+    }
+}
 
-#[derive(Debug)]
+// Compact, since every token in the printed token stream carries one.
+impl fmt::Debug for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum Token {
-    ID(String, usize),
-    Print(usize),
-    Num(String, usize),
-    Semicolon(usize),
-    Assign(usize),
-    RightParen(usize),
-    LeftParen(usize),
-    RightCurl(usize),
-    LeftCurl(usize),
-    Comma(usize),
-    Add(usize),
-    Subtract(usize),
-    Multiply(usize),
-    Divide(usize),
-    EndOfFile(usize),
-    For(usize),
-    LessThan(usize),
-    GreaterThan(usize),
+    ID(String, Span),
+    Print(Span),
+    Num(String, Span),
+    Semicolon(Span),
+    Assign(Span),
+    RightParen(Span),
+    LeftParen(Span),
+    RightCurl(Span),
+    LeftCurl(Span),
+    Comma(Span),
+    Add(Span),
+    Subtract(Span),
+    Multiply(Span),
+    Divide(Span),
+    EndOfFile(Span),
+    For(Span),
+    LessThan(Span),
+    GreaterThan(Span),
+    Write(Span),
+    Str(String, Span),
+    Char(char, Span),
+    PrintChar(Span),
+    I64(Span),
+    Import(Span),
+    Export(Span),
+    Asm(Span),
+    Out(Span),
+    In(Span),
+    Clobber(Span),
+    Const(Span),
 }
 
 impl Token {
@@ -43,206 +90,559 @@ impl Token {
             Token::For(_) => "For",
             Token::LessThan(_) => "LessThan",
             Token::GreaterThan(_) => "GreaterThan",
+            Token::Write(_) => "Write",
+            Token::Str(_, _) => "Str",
+            Token::Char(_, _) => "Char",
+            Token::PrintChar(_) => "PrintChar",
+            Token::I64(_) => "I64",
+            Token::Import(_) => "Import",
+            Token::Export(_) => "Export",
+            Token::Asm(_) => "Asm",
+            Token::Out(_) => "Out",
+            Token::In(_) => "In",
+            Token::Clobber(_) => "Clobber",
+            Token::Const(_) => "Const",
         }
     }
-    pub fn line(&self) -> usize {
+    pub fn span(&self) -> &Span {
         match self {
-            Token::ID(_, line) => *line,
-            Token::Print(line) => *line,
-            Token::Num(_, line) => *line,
-            Token::Semicolon(line) => *line,
-            Token::Assign(line) => *line,
-            Token::RightParen(line) => *line,
-            Token::LeftParen(line) => *line,
-            Token::RightCurl(line) => *line,
-            Token::LeftCurl(line) => *line,
-            Token::Comma(line) => *line,
-            Token::Add(line) => *line,
-            Token::Subtract(line) => *line,
-            Token::Multiply(line) => *line,
-            Token::Divide(line) => *line,
-            Token::EndOfFile(line) => *line,
-            Token::For(line) => *line,
-            Token::LessThan(line) => *line,
-            Token::GreaterThan(line) => *line,
+            Token::ID(_, span) => span,
+            Token::Print(span) => span,
+            Token::Num(_, span) => span,
+            Token::Semicolon(span) => span,
+            Token::Assign(span) => span,
+            Token::RightParen(span) => span,
+            Token::LeftParen(span) => span,
+            Token::RightCurl(span) => span,
+            Token::LeftCurl(span) => span,
+            Token::Comma(span) => span,
+            Token::Add(span) => span,
+            Token::Subtract(span) => span,
+            Token::Multiply(span) => span,
+            Token::Divide(span) => span,
+            Token::EndOfFile(span) => span,
+            Token::For(span) => span,
+            Token::LessThan(span) => span,
+            Token::GreaterThan(span) => span,
+            Token::Write(span) => span,
+            Token::Str(_, span) => span,
+            Token::Char(_, span) => span,
+            Token::PrintChar(span) => span,
+            Token::I64(span) => span,
+            Token::Import(span) => span,
+            Token::Export(span) => span,
+            Token::Asm(span) => span,
+            Token::Out(span) => span,
+            Token::In(span) => span,
+            Token::Clobber(span) => span,
+            Token::Const(span) => span,
+        }
+    }
+    // This token as an error message shows it: its text if it has any.
+    pub fn describe(&self) -> String {
+        match self {
+            Token::ID(val, _) | Token::Num(val, _) => format!("`{}`", val),
+            Token::Str(val, _) => format!("\"{}\"", val),
+            Token::Char(val, _) => format!("'{}'", val.escape_default()),
+            _ => describe_token(self.name()).to_string(),
         }
     }
     pub fn val(&self) -> String {
         match self {
             Token::ID(val, _) => val.clone(),
             Token::Num(val, _) => val.clone(),
+            Token::Str(val, _) => val.clone(),
+            Token::Char(val, _) => (*val as u32).to_string(),
             _ => String::new(),
         }
     }
 }
 
-pub fn is_delimiter(chr: char) -> bool {
-    if chr == ' '
-        || chr == '('
-        || chr == ')'
-        || chr == '}'
-        || chr == '{'
-        || chr == '/'
-        || chr == '+'
-        || chr == '-'
-        || chr == '$'
-        || chr == '*'
-        || chr == ';'
-        || chr == ','
-        || chr == '<'
-        || chr == '>'
-    {
-        return true;
-    } else {
-        return false;
+// How a kind of token reads in an error message.
+pub fn describe_token(name: &str) -> &'static str {
+    match name {
+        "ID" => "a name",
+        "Num" => "a number",
+        "Str" => "a string",
+        "Char" => "a character",
+        "EndOfFile" => "the end of the file",
+        "Print" => "`print`",
+        "For" => "`for`",
+        "Write" => "`write`",
+        "PrintChar" => "`print_char`",
+        "I64" => "`i64`",
+        "Import" => "`import`",
+        "Export" => "`export`",
+        "Asm" => "`asm`",
+        "Out" => "`out`",
+        "In" => "`in`",
+        "Clobber" => "`clobber`",
+        "Const" => "`const`",
+        "Semicolon" => "`;`",
+        "Assign" => "`:=`",
+        "RightParen" => "`)`",
+        "LeftParen" => "`(`",
+        "RightCurl" => "`}`",
+        "LeftCurl" => "`{`",
+        "Comma" => "`,`",
+        "Add" => "`+`",
+        "Subtract" => "`-`",
+        "Multiply" => "`*`",
+        "Divide" => "`/`",
+        "LessThan" => "`<`",
+        "GreaterThan" => "`>`",
+        _ => "an unknown token",
     }
 }
 
-pub fn make_delim_token(chr: char, line: usize) -> (Token, bool) {
+fn make_delim_token(chr: char, span: Span) -> Option<Token> {
     match chr {
-        '(' => (Token::LeftParen(line), true),
-        ')' => (Token::RightParen(line), true),
-        '{' => (Token::LeftCurl(line), true),
-        '}' => (Token::RightCurl(line), true),
-        '/' => (Token::Divide(line), true),
-        '+' => (Token::Add(line), true),
-        '-' => (Token::Subtract(line), true),
-        '*' => (Token::Multiply(line), true),
-        ';' => (Token::Semicolon(line), true),
-        ',' => (Token::Comma(line), true),
-        '<' => (Token::LessThan(line), true),
-        '>' => (Token::GreaterThan(line), true),
-        _ => {
-            return (Token::EndOfFile(line), false);
-        }
-    }
-}
-
-pub fn tokenize(str: String) -> Result<Vec<Token>, TokenError> {
-    let mut tok_list: Vec<Token> = Vec::new();
-    let mut state: u8 = 0;
-    let mut iter1 = str.chars();
-    let mut stop = false;
+        '(' => Some(Token::LeftParen(span)),
+        ')' => Some(Token::RightParen(span)),
+        '{' => Some(Token::LeftCurl(span)),
+        '}' => Some(Token::RightCurl(span)),
+        '/' => Some(Token::Divide(span)),
+        '+' => Some(Token::Add(span)),
+        '-' => Some(Token::Subtract(span)),
+        '*' => Some(Token::Multiply(span)),
+        ';' => Some(Token::Semicolon(span)),
+        ',' => Some(Token::Comma(span)),
+        '<' => Some(Token::LessThan(span)),
+        '>' => Some(Token::GreaterThan(span)),
+        _ => None,
+    }
+}
 
-    let mut tok = String::new();
+// Integer literals may be written in decimal, hex (0xFF) or binary (0b1010), with `_`
+// separators. They are normalised to a plain decimal string here so nothing after the
+// lexer has to care how a number was spelled. Hex and binary literals are bit patterns,
+// so anything up to 0xFFFFFFFF is allowed and wraps to the signed value `li` expects.
+// Decimal literals may go up to the i64 maximum; codegen rejects one beyond 32 bits
+// anywhere but in an i64 expression.
+fn make_num_token(tok: &str, span: Span) -> Result<Token, TokenError> {
+    let digits: String = tok.chars().filter(|c| *c != '_').collect();
+    if digits.contains('.') {
+        return Ok(Token::Num(digits, span));
+    }
 
-    let mut chr = iter1.next().unwrap_or('$');
-
-    let mut curr_line = 1;
-
-    while stop == false {
-        let asc = chr.to_ascii_lowercase() as u16;
-        match state {
-            0 => {
-                if asc >= 97 && asc <= 122 {
-                    state = 1;
-                    tok.push(chr);
-                } else if asc >= 48 && asc <= 57 {
-                    state = 3;
-                    tok.push(chr);
-                } else if is_delimiter(chr) {
-                    let (delim_token, tokenable) = make_delim_token(chr, curr_line);
-                    if tokenable {
-                        tok_list.push(delim_token);
-                    }
-                } else if asc == 58 {
-                    state = 5;
-                    tok.push(chr);
-                } else if asc == 13 || asc == 10 {
-                    if asc == 10 {
-                        curr_line = curr_line + 1;
-                    }
-                } else {
-                    return Err(TokenError::InvalidCharacter(chr));
+    let lower = digits.to_ascii_lowercase();
+    let (radix, body, bits) = if let Some(hex) = lower.strip_prefix("0x") {
+        (16, hex, 32)
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        (2, bin, 32)
+    } else {
+        (10, lower.as_str(), 64)
+    };
+
+    if body.is_empty() {
+        return Err(TokenError::MalformedNumber(tok.to_string(), span));
+    }
+    match u64::from_str_radix(body, radix) {
+        Ok(value) if bits == 32 && value <= u32::MAX as u64 => {
+            Ok(Token::Num((value as u32 as i32).to_string(), span))
+        }
+        Ok(value) if bits == 64 && value <= i64::MAX as u64 => {
+            Ok(Token::Num(value.to_string(), span))
+        }
+        _ => Err(TokenError::NumberOutOfRange(tok.to_string(), bits, span)),
+    }
+}
+
+// `out`, `in` and `clobber` are only keywords where an asm operand can start,
+// so they stay usable as names everywhere else.
+fn make_word_token(tok: &str, span: Span, asm_operand: bool) -> Token {
+    match tok {
+        "print" => Token::Print(span),
+        "for" => Token::For(span),
+        "write" => Token::Write(span),
+        "print_char" => Token::PrintChar(span),
+        "i64" => Token::I64(span),
+        "import" => Token::Import(span),
+        "export" => Token::Export(span),
+        "asm" => Token::Asm(span),
+        "out" if asm_operand => Token::Out(span),
+        "in" if asm_operand => Token::In(span),
+        "clobber" if asm_operand => Token::Clobber(span),
+        "const" => Token::Const(span),
+        _ => Token::ID(tok.to_string(), span),
+    }
+}
+
+// Characters of a reader, decoded a line at a time so the input never has to be
+// in memory all at once. A read error ends the input and is kept for the lexer.
+struct ReaderChars<'a> {
+    reader: Box<dyn BufRead + 'a>,
+    buf: String,
+    pos: usize,
+    error: Option<io::Error>,
+}
+
+impl<'a> ReaderChars<'a> {
+    fn peek(&mut self) -> Option<char> {
+        while self.pos == self.buf.len() {
+            if self.error.is_some() {
+                return None;
+            }
+            self.buf.clear();
+            self.pos = 0;
+            match self.reader.read_line(&mut self.buf) {
+                Ok(0) => return None,
+                Ok(_) => {}
+                Err(why) => {
+                    self.buf.clear();
+                    self.error = Some(why);
+                    return None;
                 }
             }
-            1 => {
-                if (asc >= 97 && asc <= 122) || (asc >= 48 && asc <= 57) || (asc == 95) {
-                    tok.push(chr);
-                } else if is_delimiter(chr) {
-                    match tok.as_str() {
-                        "print" => tok_list.push(Token::Print(curr_line)),
-                        "for" => tok_list.push(Token::For(curr_line)),
-                        _ => tok_list.push(Token::ID(tok.clone(), curr_line)),
-                    }
-                    tok.clear();
+        }
+        self.buf[self.pos..].chars().next()
+    }
 
-                    let (delim_token, tokenable) = make_delim_token(chr, curr_line);
-                    if tokenable {
-                        tok_list.push(delim_token);
-                    }
-                    state = 0;
-                } else if asc == 58 {
-                    match tok.as_str() {
-                        "print" => tok_list.push(Token::Print(curr_line)),
-                        "for" => tok_list.push(Token::For(curr_line)),
-                        _ => tok_list.push(Token::ID(tok.clone(), curr_line)),
-                    }
-                    tok.clear();
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+}
+
+// A cursor over the source that knows where the next character is.
+struct Scanner<'a> {
+    chars: ReaderChars<'a>,
+    // byte offset of the next character
+    pos: usize,
+    line: usize,
+    col: usize,
+    source_map: &'a SourceMap,
+    errors: Vec<TokenError>,
+}
 
-                    state = 5;
-                    tok.push(chr);
-                } else if asc == 13 || asc == 10 {
-                } else {
-                    return Err(TokenError::InvalidCharacter(chr));
+impl<'a> Scanner<'a> {
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        self.pos += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
+        }
+        Some(c)
+    }
+
+    fn take_while(&mut self, tok: &mut String, accept: impl Fn(char) -> bool) {
+        while let Some(chr) = self.peek().filter(|c| accept(*c)) {
+            tok.push(chr);
+            self.bump();
+        }
+    }
+
+    // The span of the next character; empty at the end of input.
+    fn here(&mut self) -> Span {
+        let len = self.peek().map_or(0, |c| c.len_utf8());
+        Span {
+            file: self.source_map.file_index(self.line),
+            start: self.pos,
+            end: self.pos + len,
+            line: self.line,
+            col: self.col,
+        }
+    }
+
+    // From `start` up to, but not including, the next character.
+    fn span_from(&mut self, start: &Span) -> Span {
+        Span {
+            end: self.pos,
+            ..start.clone()
+        }
+    }
+}
+
+// Produces tokens on demand from a `&str` (as bytes) or any other BufRead. A bad
+// character or literal comes out as an error and is skipped, so scanning always
+// goes on to the end; a malformed literal is followed by a placeholder token
+// for the parser. The last token is always EndOfFile.
+pub struct Lexer<'a> {
+    scanner: Scanner<'a>,
+    // what the last scanned token produced: its errors, then the token itself
+    pending: VecDeque<Result<Token, TokenError>>,
+    finished: bool,
+    // one entry per `asm(` being read, innermost last: how many parentheses are
+    // open inside it, which is 0 between `asm` and its `(` and 1 between operands
+    asm_parens: Vec<usize>,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(reader: impl BufRead + 'a, source_map: &'a SourceMap) -> Lexer<'a> {
+        let chars = ReaderChars {
+            reader: Box::new(reader),
+            buf: String::new(),
+            pos: 0,
+            error: None,
+        };
+        Lexer {
+            scanner: Scanner {
+                chars,
+                pos: 0,
+                line: 1,
+                col: 1,
+                source_map,
+                errors: Vec::new(),
+            },
+            pending: VecDeque::new(),
+            finished: false,
+            asm_parens: Vec::new(),
+        }
+    }
+
+    // The item `next` will return, without consuming it.
+    pub fn peek(&mut self) -> Option<&Result<Token, TokenError>> {
+        if self.pending.is_empty() {
+            self.fill();
+        }
+        self.pending.front()
+    }
+
+    // The token `n` places after the one `peek` returns, looking past any errors
+    // between them. Syntax error messages use these to see if inserting or
+    // dropping a token would help.
+    pub fn peek_after(&mut self, n: usize) -> Option<&Token> {
+        while self.pending.iter().filter(|item| item.is_ok()).count() <= n && !self.finished {
+            self.scan_one();
+        }
+        self.pending
+            .iter()
+            .filter_map(|item| item.as_ref().ok())
+            .nth(n)
+    }
+
+    fn fill(&mut self) {
+        while self.pending.is_empty() && !self.finished {
+            self.scan_one();
+        }
+    }
+
+    fn scan_one(&mut self) {
+        let token = match self.scanner.peek() {
+            Some(chr) => self.scan_token(chr),
+            None => {
+                if let Some(why) = self.scanner.chars.error.take() {
+                    let span = self.scanner.here();
+                    self.scanner
+                        .errors
+                        .push(TokenError::Unreadable(why.to_string(), span));
                 }
+                self.finished = true;
+                Some(Token::EndOfFile(self.scanner.here()))
             }
-            3 => {
-                if asc >= 48 && asc <= 57 {
-                    tok.push(chr);
-                } else if asc == 46 {
-                    state = 4;
-                    tok.push(chr);
-                } else if is_delimiter(chr) {
-                    tok_list.push(Token::Num(tok.clone(), curr_line));
-                    tok.clear();
-
-                    let (delim_token, tokenable) = make_delim_token(chr, curr_line);
-                    if tokenable {
-                        tok_list.push(delim_token);
-                    }
+        };
+        self.pending.extend(self.scanner.errors.drain(..).map(Err));
+        if let Some(token) = token {
+            self.track_asm(&token);
+            self.pending.push_back(Ok(token));
+        }
+    }
 
-                    state = 0;
-                } else if asc == 13 || asc == 10 {
-                } else {
-                    return Err(TokenError::InvalidCharacter(chr));
-                }
+    fn track_asm(&mut self, token: &Token) {
+        match (token, self.asm_parens.last_mut()) {
+            (Token::Asm(_), _) => self.asm_parens.push(0),
+            (Token::LeftParen(_), Some(depth)) => *depth += 1,
+            (Token::RightParen(_), Some(depth)) if *depth > 1 => *depth -= 1,
+            // the asm's own `)`, or an `asm` without a `(`, which the parser rejects
+            (Token::RightParen(_), Some(_)) | (_, Some(0)) => {
+                self.asm_parens.pop();
             }
-            4 => {
-                if asc >= 48 && asc <= 57 {
-                    tok.push(chr);
-                } else if is_delimiter(chr) {
-                    tok_list.push(Token::Num(tok.clone(), curr_line));
-                    tok.clear();
-
-                    let (delim_token, tokenable) = make_delim_token(chr, curr_line);
-                    if tokenable {
-                        tok_list.push(delim_token);
-                    }
+            _ => {}
+        }
+    }
 
-                    state = 0;
-                } else if asc == 13 || asc == 10 {
-                } else {
-                    return Err(TokenError::InvalidCharacter(chr));
-                }
+    fn scan_token(&mut self, chr: char) -> Option<Token> {
+        let scanner = &mut self.scanner;
+        let start = scanner.here();
+        if chr.is_whitespace() {
+            scanner.bump();
+            None
+        } else if chr.is_ascii_alphabetic() {
+            let asm_operand = self.asm_parens.last() == Some(&1);
+            Some(scan_word(scanner, start, asm_operand))
+        } else if chr.is_ascii_digit() {
+            Some(scan_number(scanner, start))
+        } else if chr == ':' {
+            scanner.bump();
+            if scanner.peek() == Some('=') {
+                scanner.bump();
+                Some(Token::Assign(scanner.span_from(&start)))
+            } else {
+                scanner.errors.push(TokenError::DanglingColon(start));
+                None
+            }
+        } else if chr == '"' {
+            Some(scan_string(scanner, start))
+        } else if chr == '\'' {
+            Some(scan_char(scanner, start))
+        } else if let Some(delim_token) = make_delim_token(chr, start.clone()) {
+            scanner.bump();
+            Some(delim_token)
+        } else {
+            scanner.bump();
+            scanner
+                .errors
+                .push(TokenError::InvalidCharacter(chr, start));
+            None
+        }
+    }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Result<Token, TokenError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pending.is_empty() {
+            self.fill();
+        }
+        self.pending.pop_front()
+    }
+}
+
+fn scan_word(scanner: &mut Scanner, start: Span, asm_operand: bool) -> Token {
+    let mut tok = String::new();
+    // `.` joins a module name and a member, as in math.pi
+    scanner.take_while(&mut tok, |c| {
+        c.is_ascii_alphanumeric() || c == '_' || c == '.'
+    });
+    make_word_token(&tok, scanner.span_from(&start), asm_operand)
+}
+
+fn scan_number(scanner: &mut Scanner, start: Span) -> Token {
+    let mut tok = String::new();
+    scanner.take_while(&mut tok, |c| c.is_ascii_digit() || c == '_');
+    let prefix = scanner.peek().map(|c| c.to_ascii_lowercase());
+    if tok == "0" && (prefix == Some('x') || prefix == Some('b')) {
+        tok.push(scanner.bump().unwrap());
+        if prefix == Some('x') {
+            scanner.take_while(&mut tok, |c| c.is_ascii_hexdigit() || c == '_');
+        } else {
+            scanner.take_while(&mut tok, |c| c == '0' || c == '1' || c == '_');
+        }
+    } else if scanner.peek() == Some('.') {
+        tok.push('.');
+        scanner.bump();
+        scanner.take_while(&mut tok, |c| c.is_ascii_digit());
+    }
+
+    // whatever else is glued to the number belongs to it, so that one bad
+    // literal is one error
+    let valid = tok.len();
+    scanner.take_while(&mut tok, |c| {
+        c.is_ascii_alphanumeric() || c == '_' || c == '.'
+    });
+    let span = scanner.span_from(&start);
+    let error = match tok[valid..].chars().next() {
+        Some('.') if tok[..valid].contains('.') => {
+            Some(TokenError::ExtraDecimalPoint(tok.clone(), span.clone()))
+        }
+        Some(digit) => Some(TokenError::InvalidDigit(tok.clone(), digit, span.clone())),
+        None if tok.ends_with('.') => Some(TokenError::MissingFraction(tok.clone(), span.clone())),
+        None => None,
+    };
+    let result = match error {
+        Some(error) => Err(error),
+        None => make_num_token(&tok, span.clone()),
+    };
+    result.unwrap_or_else(|error| {
+        scanner.errors.push(error);
+        Token::Num(String::from("0"), span)
+    })
+}
+
+// Escapes stay as written in the token; they are resolved in .asciiz. A string
+// left open at the end of its line is reported and ends there.
+fn scan_string(scanner: &mut Scanner, start: Span) -> Token {
+    let mut tok = String::new();
+    scanner.bump();
+    loop {
+        match scanner.peek() {
+            None | Some('\n') | Some('\r') => {
+                let span = scanner.span_from(&start);
+                scanner
+                    .errors
+                    .push(TokenError::UnterminatedString(span.clone()));
+                return Token::Str(tok, span);
             }
-            5 => {
-                if asc == 61 {
-                    tok.clear();
-                    tok_list.push(Token::Assign(curr_line));
-
-                    state = 0;
-                } else {
-                    return Err(TokenError::InvalidCharacter(chr));
+            Some('"') => {
+                scanner.bump();
+                return Token::Str(tok, scanner.span_from(&start));
+            }
+            Some('\\') => {
+                scanner.bump();
+                match scanner.peek() {
+                    Some(chr @ ('n' | 't' | '"' | '\\')) => {
+                        tok.push('\\');
+                        tok.push(chr);
+                        scanner.bump();
+                    }
+                    Some(chr) if chr != '\n' && chr != '\r' => {
+                        let span = scanner.here();
+                        scanner.errors.push(TokenError::InvalidEscape(chr, span));
+                        scanner.bump();
+                    }
+                    // the next round reports the string as unterminated
+                    _ => {}
                 }
             }
-            _ => println!("No matching state"),
+            Some(chr) => {
+                tok.push(chr);
+                scanner.bump();
+            }
+        }
+    }
+}
+
+// Character literals are resolved here, since they become plain numbers. The
+// literal runs to the closing quote or the end of the line.
+fn scan_char(scanner: &mut Scanner, start: Span) -> Token {
+    scanner.bump();
+    let mut value = Vec::new();
+    let mut escape_error = false;
+    let mut closed = false;
+    while let Some(chr) = scanner.peek() {
+        if chr == '\n' || chr == '\r' {
+            break;
         }
-        if chr == '$' {
-            stop = true;
+        scanner.bump();
+        match chr {
+            '\'' => {
+                closed = true;
+                break;
+            }
+            '\\' => {
+                let escaped = match scanner.peek() {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('0') => '\0',
+                    Some(chr @ ('\'' | '"' | '\\')) => chr,
+                    Some(chr) if chr != '\n' && chr != '\r' => {
+                        let span = scanner.here();
+                        scanner.errors.push(TokenError::InvalidEscape(chr, span));
+                        escape_error = true;
+                        chr
+                    }
+                    _ => continue,
+                };
+                scanner.bump();
+                value.push(escaped);
+            }
+            _ => value.push(chr),
         }
-        chr = iter1.next().unwrap_or('$');
     }
-    tok_list.push(Token::EndOfFile(curr_line));
-    Ok(tok_list)
+    let span = scanner.span_from(&start);
+    if !closed || (value.len() != 1 && !escape_error) {
+        scanner
+            .errors
+            .push(TokenError::InvalidCharLiteral(span.clone()));
+    }
+    Token::Char(value.first().copied().unwrap_or('\0'), span)
 }
@@ -9,389 +9,85 @@ use std::env;
 use std::fmt::format;
 use std::hash::Hash;
 use std::sync::Arc;
-use std::{fs::File, io::Read, path::Path};
 
 mod errors;
-use ast::{CodeGenerator, Stm};
-use errors::throw_err;
+use ast::{collect_i64_vars, CodeGenerator};
+use errors::CompileError;
 
 mod lex;
-use lex::{tokenize, Token};
 
 mod ir_code_gen;
-use ir_code_gen::{gen_line, StackItem};
+
+mod parser;
 
 mod ast;
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
+mod options;
+use options::Options;
 
-    let file_path = &args[1];
+mod runtime;
 
-    let path = Path::new(file_path);
-    let display = path.display();
+mod preprocess;
+use preprocess::SourceMap;
 
-    let mut file = match File::open(&path) {
-        Err(why) => panic!("couldn't open {}: {}", display, why),
-        Ok(file) => file,
-    };
+mod modules;
+use modules::load_program;
 
-    let mut str = String::new();
-    match file.read_to_string(&mut str) {
-        Err(why) => panic!("couldn't open {}: {}", display, why),
-        Ok(_) => print!("{} contains: \n{}\n\n", display, str),
-    };
+mod intrinsics;
+
+mod grammar;
+use grammar::{build_table, load_table};
+
+mod trace;
+
+mod descent;
 
-    let final_tok_list = match tokenize(str) {
-        Ok(final_tok_list) => final_tok_list,
-        Err(token_err) => panic!("ERROR: {}", token_err),
+mod cst;
+
+mod semantic;
+use semantic::InitCheck;
+
+#[cfg(test)]
+mod test_support;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    let options = match Options::from_args(&args) {
+        Ok(options) => options,
+        Err(why) => panic!("{}", why),
     };
 
-    println!("Token Stream:");
-    for tok in &final_tok_list {
-        print!("{:?} ", tok);
-    }
-    println!();
-
-    // let mut table = [[""; 27]; 50];
-
-    let table: [[&str; 27]; 50] = [
-        [
-            "s1", "s2", "", "", "", "", "", "", "", "", "", "", "", "", "s10", "s3", "", "", "g4",
-            "g5", "g6", "", "", "", "", "", "",
-        ],
-        [
-            "", "", "", "", "s7", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "",
-            "", "", "", "", "", "",
-        ],
-        [
-            "", "", "", "", "", "", "s8", "", "", "", "", "", "", "", "", "", "", "", "", "", "",
-            "", "", "", "", "", "",
-        ],
-        [
-            "", "", "", "", "", "", "s9", "", "", "", "", "", "", "", "", "", "", "", "", "", "",
-            "", "", "", "", "", "",
-        ],
-        [
-            "", "", "", "", "", "", "", "", "", "", "", "", "", "", "s10", "", "", "", "", "", "",
-            "", "", "", "", "", "",
-        ],
-        [
-            "r1", "r1", "r1", "s11", "r1", "r1", "r1", "r1", "r1", "r1", "r1", "r1", "r1", "r1",
-            "r1", "r1", "r1", "r1", "", "", "", "", "", "", "", "", "",
-        ],
-        [
-            "r3", "r3", "r3", "r3", "r3", "r3", "r3", "r3", "r3", "r3", "r3", "r3", "r3", "r3",
-            "r3", "r3", "r3", "r3", "", "", "", "", "", "", "", "", "",
-        ],
-        [
-            "s12", "", "s13", "", "", "", "s14", "", "", "", "", "", "", "", "", "", "", "", "",
-            "", "", "", "g15", "", "g16", "g17", "",
-        ],
-        [
-            "s12", "", "s13", "", "", "", "s14", "", "", "", "", "", "", "", "", "", "", "", "",
-            "", "", "g18", "g19", "", "g16", "g17", "",
-        ],
-        [
-            "s1", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "",
-            "g20", "", "", "", "", "", "",
-        ],
-        [
-            "acc", "acc", "acc", "acc", "acc", "acc", "acc", "acc", "acc", "acc", "acc", "acc",
-            "acc", "acc", "acc", "acc", "acc", "acc", "", "", "", "", "", "", "", "", "",
-        ],
-        [
-            "s1", "s2", "", "", "", "", "", "", "", "", "", "", "", "", "", "s3", "", "", "",
-            "g21", "g6", "", "", "", "", "", "",
-        ],
-        [
-            "r9", "r9", "r9", "r9", "r9", "r9", "r9", "r9", "r9", "r9", "s22", "s23", "s24", "s25",
-            "r9", "r9", "r9", "r9", "", "", "", "", "", "", "", "", "g26",
-        ],
-        [
-            "r10", "r10", "r10", "r10", "r10", "r10", "r10", "r10", "r10", "r10", "s22", "s23",
-            "s24", "s25", "r10", "r10", "r10", "r10", "", "", "", "", "", "", "", "", "g27",
-        ],
-        [
-            "s1", "s2", "", "", "", "", "", "", "", "", "", "", "", "", "", "s3", "", "", "",
-            "g28", "g6", "", "", "", "", "", "",
-        ],
-        [
-            "r6", "r6", "r6", "r6", "r6", "r6", "r6", "r6", "r6", "r6", "r6", "r6", "r6", "r6",
-            "r6", "r6", "r6", "r6", "", "", "", "", "", "", "", "", "",
-        ],
-        [
-            "r11", "r11", "r11", "r11", "r11", "r11", "r11", "r11", "r11", "r11", "r11", "r11",
-            "r11", "r11", "r11", "r11", "r11", "r11", "", "", "", "", "", "", "", "", "",
-        ],
-        [
-            "r12", "r12", "r12", "r12", "r12", "r12", "r12", "r12", "r12", "r12", "r12", "r12",
-            "r12", "r12", "r12", "r12", "r12", "r12", "", "", "", "", "", "", "", "", "",
-        ],
-        [
-            "", "", "", "", "", "s29", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "",
-            "", "", "", "", "", "",
-        ],
-        [
-            "r8", "r8", "r8", "r8", "r8", "r8", "r8", "r8", "r8", "s30", "r8", "r8", "r8", "r8",
-            "r8", "r8", "r8", "r8", "", "", "", "", "", "", "", "", "",
-        ],
-        [
-            "", "", "", "s31", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "",
-            "", "", "", "", "", "",
-        ],
-        [
-            "r2", "r2", "r2", "r2", "r2", "r2", "r2", "r2", "r2", "r2", "r2", "r2", "r2", "r2",
-            "r2", "r2", "r2", "r2", "", "", "", "", "", "", "", "", "",
-        ],
-        [
-            "r18", "r18", "r18", "r18", "r18", "r18", "r18", "r18", "r18", "r18", "r18", "r18",
-            "r18", "r18", "r18", "r18", "r18", "r18", "", "", "", "", "", "", "", "", "",
-        ],
-        [
-            "r19", "r19", "r19", "r19", "r19", "r19", "r19", "r19", "r19", "r19", "r19", "r19",
-            "r19", "r19", "r19", "r19", "r19", "r19", "", "", "", "", "", "", "", "", "",
-        ],
-        [
-            "r20", "r20", "r20", "r20", "r20", "r20", "r20", "r20", "r20", "r20", "r20", "r20",
-            "r20", "r20", "r20", "r20", "r20", "r20", "", "", "", "", "", "", "", "", "",
-        ],
-        [
-            "r21", "r21", "r21", "r21", "r21", "r21", "r21", "r21", "r21", "r21", "r21", "r21",
-            "r21", "r21", "r21", "r21", "r21", "r21", "", "", "", "", "", "", "", "", "",
-        ],
-        [
-            "s12", "", "s13", "", "", "", "s14", "", "", "", "", "", "", "", "", "", "", "", "",
-            "", "", "", "g32", "", "g16", "g17", "",
-        ],
-        [
-            "s12", "", "s13", "", "", "", "s14", "", "", "", "", "", "", "", "", "", "", "", "",
-            "", "", "", "g33", "", "g16", "g17", "",
-        ],
-        [
-            "", "", "", "s11", "", "", "", "", "", "s34", "", "", "", "", "", "", "", "", "", "",
-            "", "", "", "", "", "", "",
-        ],
-        [
-            "r4", "r4", "r4", "r4", "r4", "r4", "r4", "r4", "r4", "r4", "r4", "r4", "r4", "r4",
-            "r4", "r4", "r4", "r4", "", "", "", "", "", "", "", "", "",
-        ],
-        [
-            "s12", "", "s13", "", "", "", "s14", "", "", "", "", "", "", "", "", "", "", "", "",
-            "", "", "g35", "g19", "", "g16", "g17", "",
-        ],
-        [
-            "s12", "", "s13", "", "", "", "s14", "", "", "", "", "", "", "", "", "", "", "", "",
-            "", "", "", "g36", "g37", "g16", "g17", "",
-        ],
-        [
-            "r15", "r15", "r15", "r15", "r15", "r15", "r15", "r15", "r15", "r15", "r15", "r15",
-            "r15", "r15", "r15", "r15", "r15", "r15", "", "", "", "", "", "", "", "", "",
-        ],
-        [
-            "r16", "r16", "r16", "r16", "r16", "r16", "r16", "r16", "r16", "r16", "r16", "r16",
-            "r16", "r16", "r16", "r16", "r16", "r16", "", "", "", "", "", "", "", "", "",
-        ],
-        [
-            "s12", "", "s13", "", "", "", "s14", "", "", "", "", "", "", "", "", "", "", "", "",
-            "", "", "", "g38", "", "g16", "g17", "",
-        ],
-        [
-            "r7", "r7", "r7", "r7", "r7", "r7", "r7", "r7", "r7", "r7", "r7", "r7", "r7", "r7",
-            "r7", "r7", "r7", "r7", "", "", "", "", "", "", "", "", "",
-        ],
-        [
-            "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "s39", "s40", "", "",
-            "", "", "", "", "", "", "",
-        ],
-        [
-            "", "", "", "s41", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "",
-            "", "", "", "", "", "",
-        ],
-        [
-            "", "", "", "", "", "s42", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "",
-            "", "", "", "", "", "",
-        ],
-        [
-            "s12", "", "s13", "", "", "", "s14", "", "", "", "", "", "", "", "", "", "", "", "",
-            "", "", "", "g43", "", "g16", "g17", "",
-        ],
-        [
-            "s12", "", "s13", "", "", "", "s14", "", "", "", "", "", "", "", "", "", "", "", "",
-            "", "", "", "g44", "", "g16", "g17", "",
-        ],
-        [
-            "s1", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "",
-            "g45", "", "", "", "", "", "",
-        ],
-        [
-            "r17", "r17", "r17", "r17", "r17", "r17", "r17", "r17", "r17", "r17", "r17", "r17",
-            "r17", "r17", "r17", "r17", "r17", "r17", "", "", "", "", "", "", "", "", "",
-        ],
-        [
-            "r13", "r13", "r13", "r13", "r13", "r13", "r13", "r13", "r13", "r13", "r13", "r13",
-            "r13", "r13", "r13", "r13", "r13", "r13", "", "", "", "", "", "", "", "", "",
-        ],
-        [
-            "r14", "r14", "r14", "r14", "r14", "r14", "r14", "r14", "r14", "r14", "r14", "r14",
-            "r14", "r14", "r14", "r14", "r14", "r14", "", "", "", "", "", "", "", "", "",
-        ],
-        [
-            "", "", "", "", "", "s46", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "",
-            "", "", "", "", "", "",
-        ],
-        [
-            "", "", "", "", "", "", "", "", "s47", "", "", "", "", "", "", "", "", "", "", "", "",
-            "", "", "", "", "", "",
-        ],
-        [
-            "s1", "s2", "", "", "", "", "", "", "", "", "", "", "", "", "", "s3", "", "", "g48",
-            "g5", "g6", "", "", "", "", "", "",
-        ],
-        [
-            "", "", "", "", "", "", "", "s49", "", "", "", "", "", "", "", "", "", "", "", "", "",
-            "", "", "", "", "", "",
-        ],
-        [
-            "r5", "r5", "r5", "r5", "r5", "r5", "r5", "r5", "r5", "r5", "r5", "r5", "r5", "r5",
-            "r5", "r5", "r5", "r5", "", "", "", "", "", "", "", "", "",
-        ],
-    ];
-
-    // let contents = fs::read_to_string("./table.csv").expect("CSV read failure");
-    // let rows = contents.lines();
-    // for (i, row) in rows.enumerate() {
-    //     let splitrows = row.split(",");
-    //     for (j, value) in splitrows.enumerate() {
-    //         table[i][j] = value;
-    //     }
-    // }
-
-    let mut table_index: HashMap<&str, usize> = HashMap::new();
-
-    table_index.insert("ID", 0);
-    table_index.insert("Print", 1);
-    table_index.insert("Num", 2);
-    table_index.insert("Semicolon", 3);
-    table_index.insert("Assign", 4);
-    table_index.insert("RightParen", 5);
-    table_index.insert("LeftParen", 6);
-    table_index.insert("RightCurl", 7);
-    table_index.insert("LeftCurl", 8);
-    table_index.insert("Comma", 9);
-    table_index.insert("Add", 10);
-    table_index.insert("Subtract", 11);
-    table_index.insert("Multiply", 12);
-    table_index.insert("Divide", 13);
-    table_index.insert("EndOfFile", 14);
-    table_index.insert("For", 15);
-    table_index.insert("LessThan", 16);
-    table_index.insert("GreaterThan", 17);
-    table_index.insert("prog", 18);
-    table_index.insert("stm", 19);
-    table_index.insert("assignstm", 20);
-    table_index.insert("explist", 21);
-    table_index.insert("exp", 22);
-    table_index.insert("compexp", 23);
-    table_index.insert("opexp", 24);
-    table_index.insert("eseqexp", 25);
-    table_index.insert("binop", 26);
-
-    let mut prod_info: HashMap<usize, (&str, usize)> = HashMap::new();
-
-    prod_info.insert(1, ("prog", 1));
-    prod_info.insert(2, ("stm", 3));
-    prod_info.insert(3, ("stm", 1));
-    prod_info.insert(4, ("stm", 4));
-    prod_info.insert(5, ("stm", 11));
-    prod_info.insert(6, ("assignstm", 3));
-    prod_info.insert(7, ("explist", 3));
-    prod_info.insert(8, ("explist", 1));
-    prod_info.insert(9, ("exp", 1));
-    prod_info.insert(10, ("exp", 1));
-    prod_info.insert(11, ("exp", 1));
-    prod_info.insert(12, ("exp", 1));
-    prod_info.insert(13, ("compexp", 3));
-    prod_info.insert(14, ("compexp", 3));
-    prod_info.insert(15, ("opexp", 3));
-    prod_info.insert(16, ("opexp", 3));
-    prod_info.insert(17, ("eseqexp", 5));
-    prod_info.insert(18, ("binop", 1));
-    prod_info.insert(19, ("binop", 1));
-    prod_info.insert(20, ("binop", 1));
-    prod_info.insert(21, ("binop", 1));
-
-    let mut tok_index: usize = 0;
-    let mut curr_tok: &Token = &final_tok_list[tok_index];
-
-    let mut parse_stack: Vec<StackItem> = Vec::new();
-
-    parse_stack.push(StackItem::State(0));
-
-    let mut icg_stack: Vec<Box<dyn std::any::Any>> = Vec::new();
-
-    loop {
-        let action = if let StackItem::State(state) = parse_stack.last().unwrap() {
-            table[*state][*table_index.get(curr_tok.name()).unwrap()]
-        } else {
-            panic!("Expected a state on the parse stack");
-        };
-        // println!("{:?}", curr_tok);
-        // println!(
-        //     "{:?} {} {}",
-        //     action,
-        //     *parse_stack.last().unwrap(),
-        //     curr_tok.name()
-        // );
-        if action.starts_with("s") {
-            let next_state = action[1..].parse::<usize>().unwrap();
-            // println!("{}", next_state);
-            parse_stack.push(StackItem::Value(curr_tok.val()));
-            parse_stack.push(StackItem::State(next_state));
-            // if is_terminal(curr_tok) {
-            //     icg_stack.push(curr_tok.val());
-            // }
-
-            tok_index += 1;
-            if tok_index < final_tok_list.len() {
-                curr_tok = &final_tok_list[tok_index];
-            }
-        } else if action.starts_with("r") {
-            let prod = action[1..].parse::<usize>().unwrap();
-            // println!("REDUCE WITH RULE {:?}", prod);
-            let (result, prod_len) = prod_info.get(&prod).unwrap();
-            let pop_amount = *prod_len * 2;
-
-            let mut s = StackItem::Value(String::new());
-            for _ in 0..pop_amount {
-                s = parse_stack.pop().unwrap();
+    let parse_table = match &options.grammar_table {
+        Some(file) => load_table(file),
+        None => build_table(),
+    };
+    let parse_table = match parse_table {
+        Ok(parse_table) => parse_table,
+        Err(errors) => {
+            for why in errors {
+                println!("\nGrammar error: {}", why);
             }
-            // println!("done pop {:?}", parse_stack);
-
-            let next_action = table[if let StackItem::State(state) = parse_stack.last().unwrap() {
-                *state
-            } else {
-                panic!("Expected a state on the parse stack helP");
-            }][*table_index.get(result).unwrap()];
-            let next_state = next_action[1..].parse::<usize>().unwrap();
-
-            // println!("GOING TO STATE {:?}", next_state);
-            parse_stack.push(StackItem::Value(result.to_string()));
-            parse_stack.push(StackItem::State(next_state));
-
-            gen_line(prod, &mut icg_stack, s);
-        } else if action.eq("acc") {
-            println!("\nParse successful");
-            break;
-        } else {
-            let err = throw_err(curr_tok, &tok_index);
-            println!("\nError: {}", err);
-            return;
+            std::process::exit(1);
         }
-    }
-    // println!("Java Intermediate Code: \nStm prog = {:?};", icg_stack[0]);
+    };
 
-    let prog = &icg_stack.pop().unwrap().downcast::<Stm>().unwrap();
+    let modules = match load_program(
+        &options.file_path,
+        &options.module_path,
+        &parse_table,
+        options.trace_parse,
+        options.parser,
+        options.print_cst,
+    ) {
+        Ok(modules) => modules,
+        Err(errors) => {
+            for why in errors {
+                println!("\nError: {}", why);
+            }
+            std::process::exit(1);
+        }
+    };
 
     let mut code_gen = CodeGenerator {
         generated_code: String::new(),
@@ -399,16 +95,63 @@ fn main() {
         var_num: 0,
         label_num: 0,
         var_set: HashSet::new(),
+        strings: Vec::new(),
+        print_sep: options.print_sep.clone(),
+        checked_arith: options.checked_arith,
+        fixed_point: options.fixed_point,
+        runtime: String::new(),
+        i64_vars: HashSet::new(),
+        errors: Vec::new(),
+        source_map: SourceMap::default(),
+        call_depth: 0,
+        i64_depth: 0,
+        print_depth: 0,
+        asm_depth: 0,
+        consts: HashMap::new(),
     };
 
-    prog.accept(&mut code_gen);
+    for module in &modules {
+        collect_i64_vars(&module.prog, &mut code_gen.i64_vars);
+    }
+    if code_gen.fixed_point && !code_gen.i64_vars.is_empty() {
+        code_gen.errors.push(CompileError::I64FixedPoint);
+    }
+    // imported modules initialize first, so their code simply comes earlier in main
+    let mut init_check = (!options.allow_uninit).then(InitCheck::default);
+    let mut failed = false;
+    for module in modules {
+        code_gen.source_map = module.source_map;
+        if let Some(init_check) = &mut init_check {
+            init_check.check_stm(&module.prog, &mut code_gen.errors);
+        }
+        module.prog.accept(&mut code_gen);
+        for err in code_gen.errors.drain(..) {
+            failed = true;
+            match err.span() {
+                Some(span) => println!(
+                    "\nError: {}: {}",
+                    code_gen.source_map.locate_span(span),
+                    err
+                ),
+                None => println!("\nError: {}", err),
+            }
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
 
     println!("MIPS: ");
-    print!(".text\nmain:\n{}.data\n", code_gen.generated_code);
+    print!(
+        ".text\nmain:\n{}{}.data\n",
+        code_gen.generated_code,
+        code_gen.runtime_code()
+    );
     let var_iter = code_gen.var_set.iter();
     for x in var_iter {
         println!("{}: .word 0", x);
     }
+    print!("{}", code_gen.string_data());
     println!("newline: .asciiz \"\\n\"");
 
     let mut code_lines: Vec<String> = code_gen
@@ -426,20 +169,39 @@ fn main() {
 
     let mut operand_list: HashSet<&str> = HashSet::new();
 
+    // asm templates are opaque to the analysis: they only touch registers, so their
+    // lines just fall through. Each records the $s registers it clobbers.
+    let mut asm_clobbers: Vec<(usize, Vec<usize>)> = Vec::new();
+    let mut in_asm = false;
+
     for i in 0..code_lines.len() {
+        if let Some(clobbers) = code_lines[i].strip_prefix("# asm clobber") {
+            let saved = clobbers
+                .split_whitespace()
+                .filter_map(|reg| reg.strip_prefix("$s"))
+                .filter_map(|n| n.parse::<usize>().ok())
+                .collect();
+            asm_clobbers.push((i, saved));
+            in_asm = true;
+        }
+        if in_asm {
+            in_asm = code_lines[i] != "# endasm";
+            if i < code_lines.len() - 1 {
+                succ[i].push((i + 1).to_string());
+            }
+            continue;
+        }
         let instr_parts: Vec<&str> = code_lines[i].split(' ').collect();
         if instr_parts[0] == "j" {
             println!("JUMP OR BRANCH FOUND: {}", code_lines[i]);
-            let len = code_lines[i].len();
-            let temp_lab = &code_lines[i][len - 10..len];
+            let temp_lab = instr_parts[instr_parts.len() - 1];
 
             succ[i].push(temp_lab.to_owned());
             let val = (i + 1).to_string();
             succ[i].push(val);
             //jump does nothing for kill/gen
-        } else if instr_parts[0] == "bge" || instr_parts[0] == "ble" {
-            let len = code_lines[i].len();
-            let temp_lab = &code_lines[i][len - 10..len];
+        } else if instr_parts[0] == "bge" || instr_parts[0] == "ble" || instr_parts[0] == "beq" {
+            let temp_lab = instr_parts[instr_parts.len() - 1];
 
             succ[i].push(temp_lab.to_owned());
             let val = (i + 1).to_string();
@@ -455,11 +217,7 @@ fn main() {
                     None => continue,
                 };
                 label_instructions.insert(last, i);
-            } else if instr_parts[0] == "add"
-                || instr_parts[0] == "sub"
-                || instr_parts[0] == "mul"
-                || instr_parts[0] == "div"
-            {
+            } else if is_arith_instr(instr_parts[0]) {
                 if !is_register(instr_parts[2]) {
                     let trimmed = match instr_parts[2].strip_suffix(",") {
                         Some(x) => x,
@@ -609,12 +367,31 @@ fn main() {
 
     //remove all LW instructions. keep var identifiers in instructions -> these will be replaced inplace when allocated to a register
     //remove all SW instructions, not needed if var is not spilled.
+    // a variable live across an asm statement must not sit in a register it clobbers
+    let mut forbidden_colors: HashMap<&str, HashSet<usize>> = HashMap::new();
+    for (i, clobbers) in &asm_clobbers {
+        for var in out_list[*i].iter() {
+            forbidden_colors
+                .entry(var)
+                .or_default()
+                .extend(clobbers.iter());
+        }
+    }
+
     let k: usize = 1;
-    let reg_map = graph_coloring(&mut interference_graph, k);
+    let reg_map = graph_coloring(&mut interference_graph, k, &forbidden_colors);
 
     let mut spill_reg_num = true;
 
+    in_asm = false;
     for i in 0..code_lines.len() {
+        if code_lines[i].starts_with("# asm") {
+            in_asm = true;
+        }
+        if in_asm {
+            in_asm = code_lines[i] != "# endasm";
+            continue;
+        }
         let instr_parts: Vec<&str> = code_lines[i]
             .split(' ')
             .map(|s| s.trim_end_matches(","))
@@ -629,11 +406,7 @@ fn main() {
                 println!("NEW LINE {}", new_line);
                 code_lines[i] = new_line;
             }
-        } else if instr_parts[0] == "add"
-            || instr_parts[0] == "sub"
-            || instr_parts[0] == "mul"
-            || instr_parts[0] == "div"
-        {
+        } else if is_arith_instr(instr_parts[0]) {
             let mut new_line = format!("{} {}, ", instr_parts[0], instr_parts[1]);
             let mut is_spilled = false;
             let mut spill_line: String = String::new();
@@ -776,6 +549,7 @@ fn main() {
     for line in code_lines {
         println!("{}", line);
     }
+    print!("{}", code_gen.runtime_code());
 
     print!(".data\n");
     for x in reg_map {
@@ -783,13 +557,16 @@ fn main() {
             println!("{}: .word 0", x.0);
         }
     }
+    print!("{}", code_gen.string_data());
     println!("newline: .asciiz \"\\n\"");
 }
 
 fn graph_coloring(
     interference_graph: &mut HashMap<&str, Vec<&str>>,
     k: usize,
+    forbidden_colors: &HashMap<&str, HashSet<usize>>,
 ) -> HashMap<String, usize> {
+    let full_graph = interference_graph.clone();
     let mut coloring_stack: Vec<(&str, Vec<&str>)> = vec![];
     let mut node: (&str, Vec<&str>) = ("", vec![]);
     let mut node_found = false;
@@ -811,12 +588,8 @@ fn graph_coloring(
         }
 
         interference_graph.remove_entry(node.0);
-        for (key, list) in interference_graph.iter_mut() {
-            for i in 0..list.len() {
-                if list[i] == node.0 {
-                    list.remove(i);
-                }
-            }
+        for list in interference_graph.values_mut() {
+            list.retain(|neighbor| *neighbor != node.0);
         }
 
         coloring_stack.push(node.clone());
@@ -828,30 +601,48 @@ fn graph_coloring(
     let mut colored_values: Vec<HashSet<&str>> = vec![HashSet::new(); k];
     let mut spilled_values: Vec<&str> = vec![];
 
+    let mut decided: HashSet<&str> = HashSet::new();
+
     while !coloring_stack.is_empty() {
-        let mut spill = true;
         node = coloring_stack.pop().unwrap();
         interference_graph.insert(node.0, node.1.clone());
+        if decided.contains(node.0) {
+            continue;
+        }
         println!("CURR {}", node.0);
 
-        'outer: for i in 0..k {
-            if node.1.is_empty() {
-                colored_values[i].insert(node.0);
-                spill = false;
-                break 'outer;
+        // the two halves of an i64 variable are colored or spilled together
+        let mut group = vec![node.0];
+        if let Some(partner) = i64_partner(node.0) {
+            if let Some((name, _)) = full_graph.get_key_value(partner.as_str()) {
+                group.push(*name);
             }
-            for neighbor in &node.1 {
-                if !colored_values[i].contains(neighbor) {
-                    println!("COLORING {} with {}", node.0, i);
-                    colored_values[i].insert(node.0);
-                    spill = false;
-                    break 'outer;
-                }
+        }
+
+        let mut colors: Vec<usize> = vec![];
+        for member in &group {
+            let neighbors = &full_graph[member];
+            let forbidden = forbidden_colors.get(member);
+            let free = (0..k).find(|i| {
+                !colors.contains(i)
+                    && !neighbors.iter().any(|n| colored_values[*i].contains(n))
+                    && !forbidden.is_some_and(|f| f.contains(i))
+            });
+            match free {
+                Some(i) => colors.push(i),
+                None => break,
             }
         }
-        if spill {
-            spilled_values.push(node.0);
+
+        if colors.len() == group.len() {
+            for (member, color) in group.iter().zip(colors) {
+                println!("COLORING {} with {}", member, color);
+                colored_values[color].insert(member);
+            }
+        } else {
+            spilled_values.extend(group.iter());
         }
+        decided.extend(group);
     }
 
     println!("COLORED: {:?}", colored_values);
@@ -872,6 +663,14 @@ fn graph_coloring(
     reg_map
 }
 
+fn i64_partner(var: &str) -> Option<String> {
+    if let Some(id) = var.strip_prefix("_lo.") {
+        Some(format!("_hi.{}", id))
+    } else {
+        var.strip_prefix("_hi.").map(|id| format!("_lo.{}", id))
+    }
+}
+
 fn in_out_list_equal(prev: &Vec<HashSet<&str>>, curr: &Vec<HashSet<&str>>) -> bool {
     for i in 0..curr.len() {
         for elem in &curr[i] {
@@ -890,6 +689,10 @@ fn is_register(s: &str) -> bool {
     return false;
 }
 
+fn is_arith_instr(s: &str) -> bool {
+    matches!(s, "add" | "addu" | "sub" | "subu" | "mul" | "div")
+}
+
 fn is_numeric(x: &str) -> bool {
     x.parse::<f64>().is_ok()
 }
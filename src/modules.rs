@@ -0,0 +1,406 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::ast::{stm_difference, AsmOperand, Exp, NodeIds, Stm};
+use crate::descent::parse_descent;
+use crate::errors::{ModuleError, ParseError, TokenError};
+use crate::grammar::ParseTable;
+use crate::lex::{Lexer, Span};
+use crate::options::ParserKind;
+use crate::parser::parse;
+use crate::preprocess::{preprocess, SourceMap};
+use crate::trace::{ParseTrace, TraceFormat};
+
+pub struct Module {
+    // None for the root file, whose variables keep their plain names
+    pub name: Option<String>,
+    pub prog: Stm,
+    pub source_map: SourceMap,
+    imports: Vec<(String, Span)>,
+    exports: HashSet<String>,
+}
+
+struct Loader<'a> {
+    parse_table: &'a ParseTable,
+    trace: Option<TraceFormat>,
+    parser: ParserKind,
+    print_cst: bool,
+    // shared by every file, so node ids are unique across the program
+    node_ids: NodeIds,
+    search_path: Vec<PathBuf>,
+    // finished modules, each after everything it imports
+    modules: Vec<Module>,
+    // modules whose imports are still being loaded, outermost first
+    loading: Vec<String>,
+}
+
+// Loads the root file and, transitively, every module it imports. Modules come
+// back in initialization order: a module's top-level statements run after those
+// of everything it imports, and the root file runs last. Every module runs once.
+pub fn load_program(
+    root: &str,
+    module_path: &[String],
+    parse_table: &ParseTable,
+    trace: Option<TraceFormat>,
+    parser: ParserKind,
+    print_cst: bool,
+) -> Result<Vec<Module>, Vec<String>> {
+    let root_path = Path::new(root);
+    // modules next to the root file are found first
+    let root_dir = match root_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut search_path = vec![root_dir.to_path_buf()];
+    search_path.extend(module_path.iter().map(PathBuf::from));
+
+    // the root file counts as loading under its own name, so a module that
+    // imports it back is reported as a cycle
+    let root_name = root_path
+        .file_stem()
+        .map_or(String::new(), |stem| stem.to_string_lossy().to_string());
+    let mut loader = Loader {
+        parse_table,
+        trace,
+        parser,
+        print_cst,
+        node_ids: NodeIds::default(),
+        search_path,
+        modules: Vec::new(),
+        loading: vec![root_name],
+    };
+    loader.load(None, root_path)?;
+
+    let exports: HashMap<String, HashSet<String>> = loader
+        .modules
+        .iter()
+        .filter_map(|m| m.name.clone().map(|name| (name, m.exports.clone())))
+        .collect();
+    for module in &mut loader.modules {
+        let imports: HashSet<&str> = module.imports.iter().map(|(m, _)| m.as_str()).collect();
+        let scope = Scope {
+            module: module.name.as_deref(),
+            imports: &imports,
+            exports: &exports,
+        };
+        scope.qualify_stm(&mut module.prog).map_err(|(err, span)| {
+            vec![format!("{}: {}", module.source_map.locate_span(&span), err)]
+        })?;
+    }
+    Ok(loader.modules)
+}
+
+impl<'a> Loader<'a> {
+    fn load(&mut self, name: Option<String>, path: &Path) -> Result<(), Vec<String>> {
+        let (str, source_map) =
+            preprocess(&path.display().to_string()).map_err(|e| vec![e.to_string()])?;
+        print!("{} contains: \n{}\n\n", path.display(), str);
+
+        // printed here rather than by a parser, so it appears once whichever ran
+        let prog = self.parse(&str, &source_map, path)?;
+        println!("\nParse successful");
+
+        let mut imports = Vec::new();
+        let mut exports = HashSet::new();
+        collect_module_stms(&prog, &mut imports, &mut exports);
+
+        for (import, span) in &imports {
+            let location = source_map.locate_span(span);
+            if import.contains('.') {
+                return Err(vec![format!(
+                    "{}: {}",
+                    location,
+                    ModuleError::InvalidName(import.clone())
+                )]);
+            }
+            if let Some(start) = self.loading.iter().position(|m| m == import) {
+                let mut chain = self.loading[start..].to_vec();
+                chain.push(import.clone());
+                return Err(vec![format!(
+                    "{}: {}",
+                    location,
+                    ModuleError::ImportCycle(chain)
+                )]);
+            }
+            if self.modules.iter().any(|m| m.name.as_ref() == Some(import)) {
+                continue;
+            }
+            let file_name = format!("{}.slp", import);
+            let found = self
+                .search_path
+                .iter()
+                .map(|dir| dir.join(&file_name))
+                .find(|candidate| candidate.is_file());
+            let module_path = match found {
+                Some(module_path) => module_path,
+                None => {
+                    let err = ModuleError::NotFound(import.clone(), self.search_path.clone());
+                    return Err(vec![format!("{}: {}", location, err)]);
+                }
+            };
+            self.loading.push(import.clone());
+            self.load(Some(import.clone()), &module_path)?;
+            self.loading.pop();
+        }
+
+        self.modules.push(Module {
+            name,
+            prog,
+            source_map,
+            imports,
+            exports,
+        });
+        Ok(())
+    }
+
+    // Parses one file with the parser chosen by --parser. With both, the trees must
+    // match; when both reject the file, each parser's errors are listed.
+    fn parse(
+        &mut self,
+        text: &str,
+        source_map: &SourceMap,
+        path: &Path,
+    ) -> Result<Stm, Vec<String>> {
+        match self.parser {
+            ParserKind::Slr => self.parse_slr(text, source_map, path),
+            ParserKind::Descent => parse_with_descent(text, source_map, &mut self.node_ids),
+            ParserKind::Compare => {
+                let slr = self.parse_slr(text, source_map, path);
+                let descent = parse_with_descent(text, source_map, &mut self.node_ids);
+                let located = |err: ModuleError| format!("{}: {}", path.display(), err);
+                match (slr, descent) {
+                    (Ok(slr), Ok(descent)) => match stm_difference(&slr, &descent) {
+                        None => Ok(slr),
+                        Some((a, b)) => Err(vec![located(ModuleError::ParsersDisagree(a, b))]),
+                    },
+                    (Err(mut errors), Ok(_)) => {
+                        errors.insert(0, located(ModuleError::ParserRejected("SLR")));
+                        Err(errors)
+                    }
+                    (Ok(_), Err(mut errors)) => {
+                        let rejected = ModuleError::ParserRejected("recursive-descent");
+                        errors.insert(0, located(rejected));
+                        Err(errors)
+                    }
+                    (Err(slr), Err(descent)) => {
+                        let slr = slr.into_iter().map(|e| format!("SLR parser: {}", e));
+                        let descent = descent
+                            .into_iter()
+                            .map(|e| format!("recursive-descent parser: {}", e));
+                        Err(slr.chain(descent).collect())
+                    }
+                }
+            }
+        }
+    }
+
+    // Token errors don't stop the parse, so a syntax error is reported with them.
+    // With --print-cst the concrete syntax tree is printed after the token stream.
+    fn parse_slr(
+        &mut self,
+        text: &str,
+        source_map: &SourceMap,
+        path: &Path,
+    ) -> Result<Stm, Vec<String>> {
+        println!("Token Stream:");
+        let mut token_errors = Vec::new();
+        let mut parse_errors = Vec::new();
+        let parsed = parse(
+            self.parse_table,
+            &mut Lexer::new(text.as_bytes(), source_map),
+            text,
+            &mut token_errors,
+            &mut parse_errors,
+            &mut self.node_ids,
+            self.trace
+                .map(|format| ParseTrace::new(format, &path.display().to_string())),
+        );
+        let errors = locate_errors(source_map, &token_errors, &parse_errors);
+        if let (true, Some((tree, _))) = (self.print_cst, &parsed) {
+            print!(
+                "\nConcrete syntax tree of {}:\n{}",
+                path.display(),
+                tree.dump()
+            );
+        }
+        match parsed {
+            Some((_, prog)) if errors.is_empty() => Ok(prog),
+            _ => Err(errors),
+        }
+    }
+}
+
+fn parse_with_descent(
+    text: &str,
+    source_map: &SourceMap,
+    ids: &mut NodeIds,
+) -> Result<Stm, Vec<String>> {
+    let mut token_errors = Vec::new();
+    let mut parse_errors = Vec::new();
+    let prog = parse_descent(
+        &mut Lexer::new(text.as_bytes(), source_map),
+        &mut token_errors,
+        &mut parse_errors,
+        ids,
+    );
+    let errors = locate_errors(source_map, &token_errors, &parse_errors);
+    if errors.is_empty() {
+        Ok(prog)
+    } else {
+        Err(errors)
+    }
+}
+
+fn locate_errors(
+    source_map: &SourceMap,
+    token_errors: &[TokenError],
+    parse_errors: &[ParseError],
+) -> Vec<String> {
+    let mut errors: Vec<String> = token_errors
+        .iter()
+        .map(|e| format!("{}: {}", source_map.locate_span(e.span()), e))
+        .collect();
+    errors.extend(
+        parse_errors
+            .iter()
+            .map(|e| format!("{}: {}", source_map.locate_span(e.span()), e)),
+    );
+    errors
+}
+
+fn collect_module_stms(
+    stm: &Stm,
+    imports: &mut Vec<(String, Span)>,
+    exports: &mut HashSet<String>,
+) {
+    match stm {
+        Stm::Compound(compound) => {
+            collect_module_stms(&compound.stm1, imports, exports);
+            collect_module_stms(&compound.stm2, imports, exports);
+        }
+        Stm::For(for_stm) => collect_module_stms(&for_stm.body, imports, exports),
+        Stm::Import(import) => imports.push((import.module.clone(), import.span.clone())),
+        Stm::Export(export) => {
+            exports.insert(export.id.clone());
+        }
+        Stm::Assign(_) | Stm::Print(_) | Stm::Asm(_) | Stm::Const(_) | Stm::Error(_) => {}
+    }
+}
+
+// Rewrites every variable of one module to the name it has in the emitted
+// program. A module's own variables become `module.name`, so private names in
+// different modules never meet in `.data`. `other.name` must refer to a module
+// this one imports and to a name that module exports.
+struct Scope<'a> {
+    module: Option<&'a str>,
+    imports: &'a HashSet<&'a str>,
+    exports: &'a HashMap<String, HashSet<String>>,
+}
+
+impl<'a> Scope<'a> {
+    fn qualify_stm(&self, stm: &mut Stm) -> Result<(), (ModuleError, Span)> {
+        match stm {
+            Stm::Assign(assign) => {
+                self.qualify_exp(&mut assign.id)?;
+                self.qualify_exp(&mut assign.exp)
+            }
+            Stm::Compound(compound) => {
+                self.qualify_stm(&mut compound.stm1)?;
+                self.qualify_stm(&mut compound.stm2)
+            }
+            Stm::Print(print) => {
+                for exp in print.exps.exps_mut() {
+                    self.qualify_exp(exp)?;
+                }
+                Ok(())
+            }
+            Stm::For(for_stm) => {
+                self.qualify_stm(&mut for_stm.iter)?;
+                self.qualify_exp(&mut for_stm.comp)?;
+                self.qualify_stm(&mut for_stm.inc)?;
+                self.qualify_stm(&mut for_stm.body)
+            }
+            Stm::Export(export) => {
+                if export.id.contains('.') {
+                    return Err((
+                        ModuleError::InvalidName(export.id.clone()),
+                        export.span.clone(),
+                    ));
+                }
+                Ok(())
+            }
+            Stm::Asm(asm) => {
+                for operand in &mut asm.operands {
+                    match operand {
+                        AsmOperand::Out(exp) | AsmOperand::In(exp) => self.qualify_exp(exp)?,
+                        AsmOperand::Clobber(_) => {}
+                    }
+                }
+                Ok(())
+            }
+            Stm::Const(const_stm) => {
+                if const_stm.id.contains('.') {
+                    return Err((
+                        ModuleError::InvalidName(const_stm.id.clone()),
+                        const_stm.span.clone(),
+                    ));
+                }
+                const_stm.id = self
+                    .qualify_name(&const_stm.id)
+                    .map_err(|err| (err, const_stm.span.clone()))?;
+                self.qualify_exp(&mut const_stm.exp)
+            }
+            Stm::Import(_) | Stm::Error(_) => Ok(()),
+        }
+    }
+
+    fn qualify_exp(&self, exp: &mut Exp) -> Result<(), (ModuleError, Span)> {
+        match exp {
+            Exp::Id(id) => {
+                id.id = self
+                    .qualify_name(&id.id)
+                    .map_err(|err| (err, id.span.clone()))?;
+                Ok(())
+            }
+            Exp::Op(op) => {
+                self.qualify_exp(&mut op.left)?;
+                self.qualify_exp(&mut op.right)
+            }
+            Exp::Eseq(eseq) => {
+                self.qualify_stm(&mut eseq.stm)?;
+                self.qualify_exp(&mut eseq.exp)
+            }
+            Exp::Comp(comp) => {
+                self.qualify_exp(&mut comp.left)?;
+                self.qualify_exp(&mut comp.right)
+            }
+            Exp::Call(call) => {
+                for arg in call.args.exps_mut() {
+                    self.qualify_exp(arg)?;
+                }
+                Ok(())
+            }
+            Exp::Num(_) | Exp::Str(_) => Ok(()),
+        }
+    }
+
+    fn qualify_name(&self, name: &str) -> Result<String, ModuleError> {
+        let parts: Vec<&str> = name.split('.').collect();
+        match parts.as_slice() {
+            [local] => Ok(match self.module {
+                Some(module) => format!("{}.{}", module, local),
+                None => local.to_string(),
+            }),
+            [module, member] if !module.is_empty() && !member.is_empty() => {
+                if !self.imports.contains(module) {
+                    return Err(ModuleError::NotImported(name.to_string()));
+                }
+                if !self.exports[*module].contains(*member) {
+                    return Err(ModuleError::NotExported(name.to_string()));
+                }
+                Ok(name.to_string())
+            }
+            _ => Err(ModuleError::InvalidName(name.to_string())),
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use crate::trace::TraceFormat;
+
+// Which parser builds the AST. Compare runs both and fails if they disagree.
+#[derive(Clone, Copy)]
+pub enum ParserKind {
+    Slr,
+    Descent,
+    Compare,
+}
+
+pub struct Options {
+    pub file_path: String,
+    pub print_sep: String,
+    pub checked_arith: bool,
+    pub fixed_point: bool,
+    pub module_path: Vec<String>,
+    // None builds the table from grammar::PRODUCTIONS
+    pub grammar_table: Option<String>,
+    pub trace_parse: Option<TraceFormat>,
+    pub parser: ParserKind,
+    // only the SLR parser builds a concrete syntax tree
+    pub print_cst: bool,
+    // skips the check for variables read before they are assigned, which then read as 0
+    pub allow_uninit: bool,
+}
+
+impl Options {
+    // Flags may appear anywhere on the command line; the first argument that is
+    // not a flag is taken as the .slp file to compile.
+    pub fn from_args(args: &[String]) -> Result<Options, String> {
+        let mut file_path = None;
+        let mut print_sep = String::from(" ");
+        let mut checked_arith = false;
+        let mut fixed_point = false;
+        let mut module_path = Vec::new();
+        let mut grammar_table = None;
+        let mut trace_parse = None;
+        let mut parser = ParserKind::Slr;
+        let mut print_cst = false;
+        let mut allow_uninit = false;
+
+        for arg in args.iter().skip(1) {
+            if let Some(sep) = arg.strip_prefix("--print-sep=") {
+                // taken as .asciiz text, so escapes like \t work the same as in source strings
+                check_print_sep(sep)?;
+                print_sep = sep.to_string();
+            } else if let Some(mode) = arg.strip_prefix("--overflow=") {
+                checked_arith = match mode {
+                    "wrap" => false,
+                    "checked" => true,
+                    _ => return Err(format!("--overflow expects wrap or checked, got {}", mode)),
+                };
+            } else if let Some(dir) = arg.strip_prefix("--module-path=") {
+                module_path.push(dir.to_string());
+            } else if let Some(file) = arg.strip_prefix("--grammar-table=") {
+                grammar_table = Some(file.to_string());
+            } else if let Some(format) = arg.strip_prefix("--trace-parse=") {
+                trace_parse = Some(match format {
+                    "text" => TraceFormat::Text,
+                    "jsonl" => TraceFormat::JsonLines,
+                    _ => {
+                        return Err(format!(
+                            "--trace-parse expects text or jsonl, got {}",
+                            format
+                        ))
+                    }
+                });
+            } else if let Some(kind) = arg.strip_prefix("--parser=") {
+                parser = match kind {
+                    "slr" => ParserKind::Slr,
+                    "descent" => ParserKind::Descent,
+                    "compare" => ParserKind::Compare,
+                    _ => {
+                        return Err(format!(
+                            "--parser expects slr, descent or compare, got {}",
+                            kind
+                        ))
+                    }
+                };
+            } else if arg == "--trace-parse" {
+                trace_parse = Some(TraceFormat::Text);
+            } else if arg == "--print-cst" {
+                print_cst = true;
+            } else if arg == "--allow-uninit" {
+                allow_uninit = true;
+            } else if arg == "--fixed-point" {
+                fixed_point = true;
+            } else if arg.starts_with("--") {
+                return Err(format!("unknown option {}", arg));
+            } else if file_path.is_none() {
+                file_path = Some(arg.clone());
+            } else {
+                return Err(format!("unexpected argument {}", arg));
+            }
+        }
+
+        if let (true, ParserKind::Descent) = (print_cst, parser) {
+            return Err(String::from(
+                "--print-cst needs the SLR parser, which --parser=descent doesn't run",
+            ));
+        }
+
+        match file_path {
+            Some(file_path) => Ok(Options {
+                file_path,
+                print_sep,
+                checked_arith,
+                fixed_point,
+                module_path,
+                grammar_table,
+                trace_parse,
+                parser,
+                print_cst,
+                allow_uninit,
+            }),
+            None => Err(String::from(
+                "usage: SLP_Lexer [--print-sep=<text>] [--overflow=wrap|checked] [--fixed-point] [--module-path=<dir>]... [--grammar-table=<file>] [--trace-parse[=text|jsonl]] [--parser=slr|descent|compare] [--print-cst] [--allow-uninit] <file.slp>",
+            )),
+        }
+    }
+}
+
+// The separator goes into the .data section as written, so it must be what a
+// string literal could hold between its quotes: no bare `"` or line break, and
+// only the escapes \n, \t, \" and \\.
+fn check_print_sep(sep: &str) -> Result<(), String> {
+    let mut chars = sep.chars();
+    while let Some(chr) = chars.next() {
+        match chr {
+            '"' => {
+                return Err(String::from(
+                    "--print-sep can't contain `\"`; write it as `\\\"`",
+                ))
+            }
+            '\n' | '\r' => {
+                return Err(String::from(
+                    "--print-sep can't contain a line break; write it as `\\n`",
+                ))
+            }
+            '\\' => match chars.next() {
+                Some('n' | 't' | '"' | '\\') => {}
+                Some(escaped) => {
+                    return Err(format!("--print-sep has an unknown escape `\\{}`", escaped))
+                }
+                None => {
+                    return Err(String::from(
+                        "--print-sep ends in a lone `\\`; write it as `\\\\`",
+                    ))
+                }
+            },
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check_print_sep;
+
+    #[test]
+    fn print_sep_accepts_string_literal_text() {
+        for sep in [" ", ", ", "\\t", "\\n", " \\\" ", "\\\\", ""] {
+            assert_eq!(check_print_sep(sep), Ok(()), "{}", sep);
+        }
+    }
+
+    #[test]
+    fn print_sep_rejects_what_would_break_asciiz() {
+        for sep in ["\"", "a\"b", "\\", "ab\\", "\\\\\\", "\\x", "\n", "a\rb"] {
+            assert!(check_print_sep(sep).is_err(), "{}", sep);
+        }
+    }
+}
@@ -0,0 +1,394 @@
+use crate::ast::{NodeIds, Stm};
+use crate::cst::{lower_node, SyntaxElement, SyntaxNode, SyntaxToken, SyntaxTree};
+use crate::errors::{ParseError, Repair, TokenError};
+use crate::grammar::{ParseTable, ERROR, TERMINALS};
+use crate::ir_code_gen::SemanticValue;
+use crate::lex::{Lexer, Span, Token};
+use crate::trace::{ParseTrace, TraceAction};
+
+// Table-driven SLR parse. Each reduction groups the symbols it pops into a
+// SyntaxNode and lowers it to its AST value with gen_line, so one pass gives
+// both the concrete syntax tree, with the trivia of `source` (the text the lexer
+// reads) attached, and the AST. Tokens are pulled from the lexer as the parse
+// needs them; lexer errors are set aside in `token_errors` and the parse goes on
+// with the next token. AST nodes get their ids from `ids`.
+//
+// A syntax error is added to `errors` and the parse recovers in panic mode: states
+// are popped until one can shift the `error` token, and input is skipped up to a
+// `;`, `}` or the end of the file. The failed statement becomes Stm::Error, so
+// every syntax error is found in one run and the rest of the tree is still
+// built. None means the parse couldn't go on at all.
+//
+// With a `trace`, every shift, reduce and error is recorded as it happens.
+pub fn parse(
+    parse_table: &ParseTable,
+    lexer: &mut Lexer,
+    source: &str,
+    token_errors: &mut Vec<TokenError>,
+    errors: &mut Vec<ParseError>,
+    ids: &mut NodeIds,
+    trace: Option<ParseTrace>,
+) -> Option<(SyntaxTree, Stm)> {
+    match parse_program(parse_table, lexer, token_errors, errors, ids, trace) {
+        Ok(Some((mut tree, prog))) => {
+            tree.attach_text(source);
+            Some((tree, prog))
+        }
+        Ok(None) => None,
+        Err(err) => {
+            errors.push(err);
+            None
+        }
+    }
+}
+
+fn parse_program(
+    parse_table: &ParseTable,
+    lexer: &mut Lexer,
+    token_errors: &mut Vec<TokenError>,
+    errors: &mut Vec<ParseError>,
+    ids: &mut NodeIds,
+    mut trace: Option<ParseTrace>,
+) -> Result<Option<(SyntaxTree, Stm)>, ParseError> {
+    let ParseTable {
+        table,
+        table_index,
+        prod_info,
+    } = parse_table;
+
+    // `symbols[i]` was shifted or reduced to in `states[i]` and led to
+    // `states[i + 1]`; every Node among them has its value on `values`
+    let mut states: Vec<usize> = vec![0];
+    let mut symbols: Vec<SyntaxElement> = Vec::new();
+    let mut values: Vec<SemanticValue> = Vec::new();
+
+    // false from a recovery until the next real token is shifted; an error in
+    // between is part of the same mistake and isn't reported again
+    let mut shifted_since_recovery = true;
+
+    let end = loop {
+        let curr_tok = peek_token(lexer, token_errors);
+        let action = &table[*states.last().unwrap()][*table_index.get(curr_tok.name()).unwrap()];
+        if action.starts_with("s") {
+            let next_state = action[1..].parse::<usize>().unwrap();
+            if let Some(trace) = &mut trace {
+                trace.record(&states, curr_tok, TraceAction::Shift(next_state));
+            }
+            symbols.push(SyntaxElement::Token(SyntaxToken::new(curr_tok)));
+            states.push(next_state);
+
+            print!("{:?} ", curr_tok);
+            lexer.next();
+            shifted_since_recovery = true;
+        } else if action.starts_with("r") {
+            let prod = action[1..].parse::<usize>().unwrap();
+            let traced_states = trace.as_ref().map(|_| states.clone());
+            let (result, prod_len) = prod_info.get(&prod).unwrap();
+            if symbols.len() < *prod_len {
+                return Err(ParseError::InternalGrammar(
+                    prod,
+                    String::from("the parse stack is shorter than the production"),
+                    curr_tok.span().clone(),
+                ));
+            }
+
+            states.truncate(states.len() - prod_len);
+            let node = SyntaxNode {
+                kind: result.to_string(),
+                prod,
+                children: symbols.split_off(symbols.len() - prod_len),
+            };
+
+            let next_action = &table[*states.last().unwrap()][*table_index.get(result).unwrap()];
+            let next_state = match next_action.strip_prefix('g').map(str::parse::<usize>) {
+                Some(Ok(next_state)) => next_state,
+                _ => {
+                    return Err(ParseError::InternalGrammar(
+                        prod,
+                        format!("no goto on {} after the reduction", result),
+                        node.span(),
+                    ))
+                }
+            };
+
+            lower_node(&node, &mut values, ids)?;
+            symbols.push(SyntaxElement::Node(node));
+            states.push(next_state);
+            if let (Some(trace), Some(traced_states), Some(value)) =
+                (&mut trace, traced_states, values.last())
+            {
+                trace.record(
+                    &traced_states,
+                    curr_tok,
+                    TraceAction::Reduce(prod, next_state, value),
+                );
+            }
+        } else if action.eq("acc") {
+            if let Some(trace) = &mut trace {
+                trace.record(&states, curr_tok, TraceAction::Accept);
+            }
+            println!("{:?} ", curr_tok);
+            break SyntaxToken::new(curr_tok);
+        } else {
+            let at_end = matches!(curr_tok, Token::EndOfFile(_));
+            if !shifted_since_recovery {
+                if let Some(trace) = &mut trace {
+                    trace.record(&states, curr_tok, TraceAction::Skip);
+                }
+                // recovering again without consuming anything could loop forever
+                if at_end {
+                    println!();
+                    return Ok(None);
+                }
+                lexer.next();
+                continue;
+            }
+            if let Some(trace) = &mut trace {
+                trace.record(&states, curr_tok, TraceAction::Error);
+            }
+            let found = curr_tok.clone();
+            let expected = expected_tokens(parse_table, &states);
+            let after: Vec<String> = (1..=2)
+                .map_while(|n| lexer.peek_after(n).map(|tok| tok.name().to_string()))
+                .collect();
+            let after: Vec<&str> = after.iter().map(String::as_str).collect();
+            let repair = suggest_repair(parse_table, &states, found.name(), &after);
+            errors.push(ParseError::UnexpectedToken(found, expected, repair));
+            if !recover(
+                parse_table,
+                &mut states,
+                &mut symbols,
+                &mut values,
+                lexer,
+                token_errors,
+            ) {
+                println!();
+                return Ok(None);
+            }
+            if let Some(trace) = &mut trace {
+                let resumed = peek_token(lexer, token_errors);
+                trace.record(&states, resumed, TraceAction::Recover);
+            }
+            shifted_since_recovery = false;
+        }
+    };
+
+    match (symbols.pop(), values.pop()) {
+        (Some(SyntaxElement::Node(prog)), Some(SemanticValue::Stm(stm))) => {
+            Ok(Some((SyntaxTree { prog, end }, *stm)))
+        }
+        _ => Err(ParseError::InternalGrammar(
+            1,
+            String::from("the program did not reduce to a Stm"),
+            Span::default(),
+        )),
+    }
+}
+
+// Pops the parse stack back to a state that can shift `error`, shifts it, then
+// skips tokens until a `;`, `}` or the end of the file that the new state can
+// act on. False if no state on the stack takes `error` (a table without the
+// column) or the input ran out first.
+fn recover(
+    parse_table: &ParseTable,
+    states: &mut Vec<usize>,
+    symbols: &mut Vec<SyntaxElement>,
+    values: &mut Vec<SemanticValue>,
+    lexer: &mut Lexer,
+    token_errors: &mut Vec<TokenError>,
+) -> bool {
+    let ParseTable {
+        table, table_index, ..
+    } = parse_table;
+    let error_column = match table_index.get(ERROR) {
+        Some(column) => *column,
+        None => return false,
+    };
+    let span = peek_token(lexer, token_errors).span().clone();
+
+    let error_state = loop {
+        let state = *states.last().unwrap();
+        if let Some(next_state) = table[state][error_column].strip_prefix('s') {
+            break next_state.parse::<usize>().unwrap();
+        }
+        if states.len() == 1 {
+            return false;
+        }
+        states.pop();
+        if let Some(SyntaxElement::Node(_)) = symbols.pop() {
+            values.pop();
+        }
+    };
+    symbols.push(SyntaxElement::Token(SyntaxToken::error(span)));
+    states.push(error_state);
+
+    loop {
+        let curr_tok = peek_token(lexer, token_errors);
+        let synchronizing = matches!(
+            curr_tok,
+            Token::Semicolon(_) | Token::RightCurl(_) | Token::EndOfFile(_)
+        );
+        if synchronizing && !table[error_state][table_index[curr_tok.name()]].is_empty() {
+            return true;
+        }
+        if let Token::EndOfFile(_) = curr_tok {
+            return false;
+        }
+        lexer.next();
+    }
+}
+
+// The terminals the parser could take next. An SLR state reduces on anything in
+// a FOLLOW set, so a terminal only counts if it would also be shifted after the
+// reductions.
+fn expected_tokens(parse_table: &ParseTable, states: &[usize]) -> Vec<String> {
+    TERMINALS
+        .iter()
+        .filter(|name| accepts(parse_table, states.to_vec(), &[**name]))
+        .map(|name| name.to_string())
+        .collect()
+}
+
+// A single token to insert before `found`, or dropping `found`, after which the
+// parser could take the next two tokens. An insertion is only suggested when it is
+// the only one that works.
+fn suggest_repair(
+    parse_table: &ParseTable,
+    states: &[usize],
+    found: &str,
+    after: &[&str],
+) -> Option<Repair> {
+    let rest: Vec<&str> = std::iter::once(found)
+        .chain(after.first().copied())
+        .collect();
+    let insertions: Vec<&str> = TERMINALS
+        .iter()
+        .copied()
+        .filter(|name| *name != "EndOfFile")
+        .filter(|name| {
+            let mut names = vec![*name];
+            names.extend(&rest);
+            accepts(parse_table, states.to_vec(), &names)
+        })
+        .collect();
+    if let [insertion] = insertions.as_slice() {
+        return Some(Repair::Insert(insertion.to_string()));
+    }
+    // without `found`, the next two tokens must fit, as `found` and the one after
+    // it must with an insertion
+    if found != "EndOfFile" && !after.is_empty() && accepts(parse_table, states.to_vec(), after) {
+        return Some(Repair::Delete);
+    }
+    None
+}
+
+// Whether the parser, with `states` on its stack, could take every token in
+// `names` without an error.
+fn accepts(parse_table: &ParseTable, mut states: Vec<usize>, names: &[&str]) -> bool {
+    let ParseTable {
+        table,
+        table_index,
+        prod_info,
+    } = parse_table;
+    for name in names {
+        let column = match table_index.get(*name) {
+            Some(column) => *column,
+            None => return false,
+        };
+        loop {
+            let action = &table[*states.last().unwrap()][column];
+            if let Some(next_state) = action.strip_prefix('s') {
+                states.push(next_state.parse().unwrap());
+                break;
+            } else if let Some(prod) = action.strip_prefix('r') {
+                let (result, prod_len) = &prod_info[&prod.parse::<usize>().unwrap()];
+                if states.len() <= *prod_len {
+                    return false;
+                }
+                states.truncate(states.len() - prod_len);
+                let goto = &table[*states.last().unwrap()][table_index[result]];
+                match goto.strip_prefix('g') {
+                    Some(next_state) => states.push(next_state.parse().unwrap()),
+                    None => return false,
+                }
+            } else {
+                return action == "acc";
+            }
+        }
+    }
+    true
+}
+
+// The lookahead token, with any errors before it moved to `token_errors`. The
+// lexer ends every input with EndOfFile, which the parser never shifts.
+pub fn peek_token<'a>(lexer: &'a mut Lexer, token_errors: &mut Vec<TokenError>) -> &'a Token {
+    while let Some(Err(_)) = lexer.peek() {
+        if let Some(Err(err)) = lexer.next() {
+            token_errors.push(err);
+        }
+    }
+    match lexer.peek() {
+        Some(Ok(tok)) => tok,
+        _ => panic!("Expected EndOfFile before the end of the token stream"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::{parse_str_slr, table};
+
+    // Each syntax error as "line:col: message".
+    fn syntax_errors(source: &str) -> Vec<String> {
+        parse_str_slr(&table(), source)
+            .errors
+            .iter()
+            .map(|err| format!("{}:{}: {}", err.span().line, err.span().col, err))
+            .collect()
+    }
+
+    fn suggestions(source: &str) -> Vec<Option<String>> {
+        syntax_errors(source)
+            .iter()
+            .map(|err| {
+                err.split_once("; try ")
+                    .map(|(_, repair)| repair.to_string())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn suggests_the_only_token_that_fits() {
+        assert_eq!(
+            syntax_errors("x := 1\ny := 2"),
+            vec!["2:1: expected `;`, `+`, `-`, `*`, `/` or the end of the file, found `y`; try inserting `;` before it"]
+        );
+        // any operator fits before `2`, so dropping it is what's suggested
+        assert_eq!(
+            suggestions("print(1 2)"),
+            vec![Some(String::from("removing it"))]
+        );
+    }
+
+    #[test]
+    fn suggests_removing_a_token_when_the_next_two_fit() {
+        assert_eq!(
+            suggestions("x := 1 2;\nprint(x)"),
+            vec![Some(String::from("removing it"))]
+        );
+        assert_eq!(
+            suggestions("print(1;)")[0],
+            Some(String::from("removing it"))
+        );
+    }
+
+    #[test]
+    fn removal_must_fit_the_token_after_next_too() {
+        // dropping the first `;` lets `b` be the operand, but `:=` can't follow it
+        assert_eq!(suggestions("a := 1 +;\nb := ;"), vec![None, None, None]);
+    }
+
+    #[test]
+    fn nothing_is_suggested_at_the_end_of_the_file() {
+        assert_eq!(suggestions("x := 1 +"), vec![None]);
+    }
+}
@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::errors::PreprocessError;
+use crate::lex::Span;
+
+// Where each line of the preprocessed text came from. The lexer and parser only
+// ever see preprocessed line numbers; errors are translated back through this.
+#[derive(Default)]
+pub struct SourceMap {
+    files: Vec<String>,
+    lines: Vec<(usize, usize)>,
+}
+
+impl SourceMap {
+    // Returns "file:line" for a 1-based line of the preprocessed text.
+    pub fn locate(&self, line: usize) -> String {
+        match line.checked_sub(1).and_then(|i| self.lines.get(i)) {
+            Some((file, orig_line)) => format!("{}:{}", self.files[*file], orig_line),
+            // the end-of-file token sits one line past the last one
+            None => match self.lines.last() {
+                Some((file, orig_line)) => format!("{}:{}", self.files[*file], orig_line + 1),
+                None => format!("{}:{}", self.files.first().map_or("", |f| f), line),
+            },
+        }
+    }
+
+    // Returns "file:line:col" for a span of the preprocessed text. Columns are
+    // those of the preprocessed line, which differ only after an expanded macro.
+    pub fn locate_span(&self, span: &Span) -> String {
+        format!("{}:{}", self.locate(span.line), span.col)
+    }
+
+    // The index in `files` of the file a preprocessed line came from.
+    pub fn file_index(&self, line: usize) -> usize {
+        match line.checked_sub(1).and_then(|i| self.lines.get(i)) {
+            Some((file, _)) => *file,
+            None => self.lines.last().map_or(0, |(file, _)| *file),
+        }
+    }
+}
+
+struct Preprocessor {
+    defines: HashMap<String, String>,
+    // files currently being expanded, outermost first, with the number of macros
+    // defined when each was entered
+    include_stack: Vec<(PathBuf, usize)>,
+    output: String,
+    map: SourceMap,
+}
+
+// Expands #include, #define, #ifdef/#ifndef/#else/#endif in the file at `path`.
+// Every source line produces exactly one output line (directives become blank
+// lines) except #include, which is replaced by the lines of the included file.
+// Lines keep their own endings, so a file without directives or macros comes out
+// unchanged; an included file's last line always ends the line it is on.
+pub fn preprocess(path: &str) -> Result<(String, SourceMap), PreprocessError> {
+    let mut pre = Preprocessor {
+        defines: HashMap::new(),
+        include_stack: Vec::new(),
+        output: String::new(),
+        map: SourceMap {
+            files: Vec::new(),
+            lines: Vec::new(),
+        },
+    };
+    pre.process_file(Path::new(path))?;
+    Ok((pre.output, pre.map))
+}
+
+impl Preprocessor {
+    fn process_file(&mut self, path: &Path) -> Result<(), PreprocessError> {
+        let display = path.display().to_string();
+        let canonical = fs::canonicalize(path)
+            .map_err(|why| PreprocessError::CouldNotRead(display.clone(), why.to_string()))?;
+        // Re-entering a file is fine if something was defined since it was entered,
+        // since an include guard can then stop it. With no new macros it would expand
+        // exactly as before, forever.
+        if let Some(start) = self
+            .include_stack
+            .iter()
+            .position(|(p, defined)| *p == canonical && *defined == self.defines.len())
+        {
+            let mut chain: Vec<String> = self.include_stack[start..]
+                .iter()
+                .map(|(p, _)| p.display().to_string())
+                .collect();
+            chain.push(canonical.display().to_string());
+            return Err(PreprocessError::IncludeCycle(chain));
+        }
+        let contents = fs::read_to_string(path)
+            .map_err(|why| PreprocessError::CouldNotRead(display.clone(), why.to_string()))?;
+
+        self.include_stack.push((canonical, self.defines.len()));
+        self.map.files.push(display);
+        let file = self.map.files.len() - 1;
+
+        // one entry per open #ifdef/#ifndef: whether its branch is being kept
+        let mut conditions: Vec<bool> = Vec::new();
+        let root = self.include_stack.len() == 1;
+        for (i, line) in contents.split_inclusive('\n').enumerate() {
+            let line_num = i + 1;
+            let (line, ending) = match line.strip_suffix("\r\n") {
+                Some(body) => (body, "\r\n"),
+                None => match line.strip_suffix('\n') {
+                    Some(body) => (body, "\n"),
+                    None if root => (line, ""),
+                    None => (line, "\n"),
+                },
+            };
+            let location = format!("{}:{}", self.map.files[file], line_num);
+            let active = conditions.iter().all(|c| *c);
+            let trimmed = line.trim_start();
+
+            let directive = match trimmed.strip_prefix('#') {
+                Some(directive) => directive,
+                None => {
+                    if active {
+                        let expanded = self.expand(line, &mut Vec::new());
+                        self.emit(&expanded, ending, file, line_num);
+                    } else {
+                        self.emit("", ending, file, line_num);
+                    }
+                    continue;
+                }
+            };
+
+            let mut parts = directive.splitn(2, char::is_whitespace);
+            let name = parts.next().unwrap_or("");
+            let rest = parts.next().unwrap_or("").trim();
+            match name {
+                "ifdef" | "ifndef" => {
+                    let macro_name = macro_name(rest, &location)?;
+                    let defined = self.defines.contains_key(macro_name);
+                    conditions.push(defined == (name == "ifdef"));
+                }
+                "else" => match conditions.last_mut() {
+                    Some(cond) => *cond = !*cond,
+                    None => return Err(PreprocessError::UnmatchedDirective("#else", location)),
+                },
+                "endif" => {
+                    if conditions.pop().is_none() {
+                        return Err(PreprocessError::UnmatchedDirective("#endif", location));
+                    }
+                }
+                _ if !active => {}
+                "define" => {
+                    let mut def = rest.splitn(2, char::is_whitespace);
+                    let macro_name = macro_name(def.next().unwrap_or(""), &location)?;
+                    let value = def.next().unwrap_or("").trim().to_string();
+                    self.defines.insert(macro_name.to_string(), value);
+                }
+                "include" => {
+                    let target = rest
+                        .strip_prefix('"')
+                        .and_then(|r| r.strip_suffix('"'))
+                        .ok_or_else(|| {
+                            PreprocessError::MalformedDirective(line.trim().to_string(), location)
+                        })?;
+                    // relative to the including file, like C's quoted form
+                    let included = path.parent().unwrap_or(Path::new("")).join(target);
+                    self.process_file(&included)?;
+                    continue;
+                }
+                _ => {
+                    return Err(PreprocessError::MalformedDirective(
+                        line.trim().to_string(),
+                        location,
+                    ))
+                }
+            }
+            self.emit("", ending, file, line_num);
+        }
+
+        if !conditions.is_empty() {
+            return Err(PreprocessError::UnterminatedConditional(
+                self.map.files[file].clone(),
+            ));
+        }
+        self.include_stack.pop();
+        Ok(())
+    }
+
+    fn emit(&mut self, text: &str, ending: &str, file: usize, line: usize) {
+        self.output.push_str(text);
+        self.output.push_str(ending);
+        self.map.lines.push((file, line));
+    }
+
+    // Replaces defined identifiers outside string and character literals. A
+    // replacement is expanded again, except for macros already being expanded,
+    // so `#define X X` cannot loop.
+    fn expand(&self, text: &str, expanding: &mut Vec<String>) -> String {
+        let mut out = String::new();
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c.is_ascii_alphabetic() {
+                let mut word = c.to_string();
+                while let Some(&next) = chars.peek() {
+                    if next.is_ascii_alphanumeric() || next == '_' {
+                        word.push(next);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                match self.defines.get(&word) {
+                    Some(value) if !expanding.contains(&word) => {
+                        expanding.push(word);
+                        out.push_str(&self.expand(value, expanding));
+                        expanding.pop();
+                    }
+                    _ => out.push_str(&word),
+                }
+            } else if c.is_ascii_digit() {
+                // keeps the letters in 0x1F or 1_000 from being read as names
+                out.push(c);
+                while let Some(&next) = chars.peek() {
+                    if next.is_ascii_alphanumeric() || next == '_' || next == '.' {
+                        out.push(next);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+            } else if c == '"' || c == '\'' {
+                out.push(c);
+                while let Some(next) = chars.next() {
+                    out.push(next);
+                    if next == '\\' {
+                        if let Some(escaped) = chars.next() {
+                            out.push(escaped);
+                        }
+                    } else if next == c {
+                        break;
+                    }
+                }
+            } else {
+                out.push(c);
+            }
+        }
+        out
+    }
+}
+
+fn macro_name<'a>(name: &'a str, location: &str) -> Result<&'a str, PreprocessError> {
+    let mut chars = name.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if valid {
+        Ok(name)
+    } else {
+        Err(PreprocessError::InvalidMacroName(
+            name.to_string(),
+            location.to_string(),
+        ))
+    }
+}
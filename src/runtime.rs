@@ -0,0 +1,157 @@
+// MIPS helper routines appended after the program by CodeGenerator::runtime_code.
+// Each one is only emitted if the program needs it.
+
+// Prints the signed 64-bit value in $a1:$a0 (hi:lo). MIPS32 has no 64-bit divide,
+// so each step divides by 10 one chunk at a time: the high word first, then the
+// two 16-bit halves of the low word, carrying the remainder down. The remainder is
+// always below 10, so every partial dividend fits in 32 bits. Digits are pushed on
+// the stack and printed in reverse. $t0-$t3 are saved because the caller may
+// still have values in them.
+pub const PRINT_I64: &str = "PRINTI64:
+addiu $sp, $sp, -16
+sw $t0, 0($sp)
+sw $t1, 4($sp)
+sw $t2, 8($sp)
+sw $t3, 12($sp)
+move $t0, $a0
+move $t1, $a1
+bgez $t1, PRINTI64POS
+li $v0, 11
+li $a0, 45
+syscall
+subu $t0, $zero, $t0
+not $t1, $t1
+sltiu $t2, $t0, 1
+addu $t1, $t1, $t2
+PRINTI64POS:
+li $t3, 0
+li $a3, 10
+PRINTI64DIV:
+divu $t1, $a3
+mflo $t1
+mfhi $t2
+sll $t2, $t2, 16
+srl $a2, $t0, 16
+or $t2, $t2, $a2
+divu $t2, $a3
+mflo $a2
+mfhi $t2
+sll $t2, $t2, 16
+andi $a0, $t0, 0xffff
+or $t2, $t2, $a0
+divu $t2, $a3
+mflo $a0
+mfhi $t2
+sll $a2, $a2, 16
+or $t0, $a2, $a0
+addiu $sp, $sp, -4
+sw $t2, 0($sp)
+addiu $t3, $t3, 1
+or $a2, $t0, $t1
+bne $a2, $zero, PRINTI64DIV
+PRINTI64OUT:
+lw $a0, 0($sp)
+addiu $sp, $sp, 4
+li $v0, 1
+syscall
+addiu $t3, $t3, -1
+bne $t3, $zero, PRINTI64OUT
+lw $t0, 0($sp)
+lw $t1, 4($sp)
+lw $t2, 8($sp)
+lw $t3, 12($sp)
+addiu $sp, $sp, 16
+jr $ra
+";
+
+// Divides Q16.16 $a0 by Q16.16 $a1 into $v0. The integer part comes from one
+// unsigned divide of the magnitudes; the 16 fraction bits are then produced by
+// restoring long division on the remainder. The remainder is below the divisor,
+// which is at most 2^31, so doubling it never leaves 32 unsigned bits. $v1 is
+// left alone because checked mode keeps the overflow line there.
+pub const FIX_DIV: &str = "FIXDIV:
+addiu $sp, $sp, -8
+sw $t0, 0($sp)
+sw $t1, 4($sp)
+xor $t0, $a0, $a1
+sra $t1, $a0, 31
+xor $a0, $a0, $t1
+subu $a0, $a0, $t1
+sra $t1, $a1, 31
+xor $a1, $a1, $t1
+subu $a1, $a1, $t1
+divu $a0, $a1
+mflo $v0
+mfhi $a2
+li $a3, 16
+FIXDIVBIT:
+sll $v0, $v0, 1
+sll $a2, $a2, 1
+sltu $t1, $a2, $a1
+bne $t1, $zero, FIXDIVNEXT
+subu $a2, $a2, $a1
+ori $v0, $v0, 1
+FIXDIVNEXT:
+addiu $a3, $a3, -1
+bne $a3, $zero, FIXDIVBIT
+bgez $t0, FIXDIVOUT
+subu $v0, $zero, $v0
+FIXDIVOUT:
+lw $t0, 0($sp)
+lw $t1, 4($sp)
+addiu $sp, $sp, 8
+jr $ra
+";
+
+// Prints the Q16.16 value in $a0 as a decimal. The fraction is rounded to four
+// digits (1/65536 is about 0.000015) and trailing zeros are dropped, keeping at
+// least one, so 2.5 prints as 2.5 and 3 as 3.0.
+pub const PRINT_FIX: &str = "PRINTFIX:
+addiu $sp, $sp, -12
+sw $t0, 0($sp)
+sw $t1, 4($sp)
+sw $t2, 8($sp)
+move $t0, $a0
+bgez $t0, PRINTFIXPOS
+li $v0, 11
+li $a0, 45
+syscall
+subu $t0, $zero, $t0
+PRINTFIXPOS:
+srl $t1, $t0, 16
+andi $t2, $t0, 0xffff
+li $a0, 10000
+mul $t2, $t2, $a0
+li $a0, 32768
+addu $t2, $t2, $a0
+srl $t2, $t2, 16
+li $a0, 10000
+bne $t2, $a0, PRINTFIXINT
+addiu $t1, $t1, 1
+li $t2, 0
+PRINTFIXINT:
+li $v0, 1
+move $a0, $t1
+syscall
+li $v0, 11
+li $a0, 46
+syscall
+li $a1, 1000
+li $a2, 10
+PRINTFIXDIGIT:
+divu $t2, $a1
+mflo $a0
+mfhi $t2
+li $v0, 1
+syscall
+beq $t2, $zero, PRINTFIXOUT
+divu $a1, $a2
+mflo $a1
+j PRINTFIXDIGIT
+PRINTFIXOUT:
+lw $t0, 0($sp)
+lw $t1, 4($sp)
+lw $t2, 8($sp)
+addiu $sp, $sp, 12
+jr $ra
+";
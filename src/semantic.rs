@@ -0,0 +1,248 @@
+use std::collections::HashSet;
+
+use crate::ast::{AsmOperand, Exp, Stm};
+use crate::errors::CompileError;
+
+// Finds reads of variables that may not have been assigned yet. Codegen gives
+// every variable a `.word 0`, so such a read would silently be 0.
+//
+// The walk follows evaluation order: an assignment's value before its target,
+// operands and arguments left to right, an `(stm, exp)`'s statement before its
+// expression, a `for` loop's start, condition, body and update, and an asm's in
+// operands before its out operands. A `for` body and update may not run at all,
+// so what they assign only counts inside the loop. Modules are checked in
+// initialization order with one InitCheck, since a module's top-level statements
+// have run by the time the files importing it read its exports.
+//
+// Each variable is reported once, at the first read found.
+#[derive(Default)]
+pub struct InitCheck {
+    // assigned on every path to the current point
+    assigned: HashSet<String>,
+    // assigned on some path, which is only short of `assigned` after a loop
+    maybe_assigned: HashSet<String>,
+    reported: HashSet<String>,
+}
+
+impl InitCheck {
+    pub fn check_stm(&mut self, stm: &Stm, errors: &mut Vec<CompileError>) {
+        match stm {
+            Stm::Assign(assign) => {
+                self.check_exp(&assign.exp, errors);
+                self.assign(&assign.id);
+            }
+            Stm::Compound(compound) => {
+                self.check_stm(&compound.stm1, errors);
+                self.check_stm(&compound.stm2, errors);
+            }
+            Stm::Print(print) => {
+                for exp in print.exps.exps() {
+                    self.check_exp(exp, errors);
+                }
+            }
+            Stm::For(for_stm) => {
+                self.check_stm(&for_stm.iter, errors);
+                self.check_exp(&for_stm.comp, errors);
+                // the condition is checked before the first pass, so the loop
+                // can end without running the body
+                let before_body = self.assigned.clone();
+                self.check_stm(&for_stm.body, errors);
+                self.check_stm(&for_stm.inc, errors);
+                self.assigned = before_body;
+            }
+            Stm::Asm(asm) => {
+                for operand in &asm.operands {
+                    if let AsmOperand::In(exp) = operand {
+                        self.check_exp(exp, errors);
+                    }
+                }
+                for operand in &asm.operands {
+                    if let AsmOperand::Out(exp) = operand {
+                        self.assign(exp);
+                    }
+                }
+            }
+            // a constant's value can only use constants, which codegen checks
+            Stm::Const(const_stm) => {
+                self.assigned.insert(const_stm.id.clone());
+                self.maybe_assigned.insert(const_stm.id.clone());
+            }
+            Stm::Import(_) | Stm::Export(_) | Stm::Error(_) => {}
+        }
+    }
+
+    fn check_exp(&mut self, exp: &Exp, errors: &mut Vec<CompileError>) {
+        match exp {
+            Exp::Id(id) => {
+                if self.assigned.contains(&id.id) || !self.reported.insert(id.id.clone()) {
+                    return;
+                }
+                if self.maybe_assigned.contains(&id.id) {
+                    errors.push(CompileError::MaybeUninitialized(
+                        id.id.clone(),
+                        id.span.clone(),
+                    ));
+                } else {
+                    errors.push(CompileError::Uninitialized(id.id.clone(), id.span.clone()));
+                }
+            }
+            Exp::Op(op) => {
+                self.check_exp(&op.left, errors);
+                self.check_exp(&op.right, errors);
+            }
+            Exp::Eseq(eseq) => {
+                self.check_stm(&eseq.stm, errors);
+                self.check_exp(&eseq.exp, errors);
+            }
+            Exp::Comp(comp) => {
+                self.check_exp(&comp.left, errors);
+                self.check_exp(&comp.right, errors);
+            }
+            Exp::Call(call) => {
+                for arg in call.args.exps() {
+                    self.check_exp(arg, errors);
+                }
+            }
+            Exp::Num(_) | Exp::Str(_) => {}
+        }
+    }
+
+    fn assign(&mut self, target: &Exp) {
+        if let Exp::Id(id) = target {
+            self.assigned.insert(id.id.clone());
+            self.maybe_assigned.insert(id.id.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InitCheck;
+    use crate::errors::CompileError;
+    use crate::test_support::{parse_str_descent, TempDir};
+
+    // The reports for one file, as ("uninit" or "maybe", name, line, col).
+    fn check(source: &str) -> Vec<(&'static str, String, usize, usize)> {
+        let prog = parse_str_descent(source).clean(source);
+        let mut errors = Vec::new();
+        InitCheck::default().check_stm(&prog, &mut errors);
+        errors.iter().map(describe).collect()
+    }
+
+    fn describe(err: &CompileError) -> (&'static str, String, usize, usize) {
+        match err {
+            CompileError::Uninitialized(name, span) => {
+                ("uninit", name.clone(), span.line, span.col)
+            }
+            CompileError::MaybeUninitialized(name, span) => {
+                ("maybe", name.clone(), span.line, span.col)
+            }
+            other => panic!("unexpected error {}", other),
+        }
+    }
+
+    fn uninit(name: &str, line: usize, col: usize) -> (&'static str, String, usize, usize) {
+        ("uninit", name.to_string(), line, col)
+    }
+
+    fn maybe(name: &str, line: usize, col: usize) -> (&'static str, String, usize, usize) {
+        ("maybe", name.to_string(), line, col)
+    }
+
+    #[test]
+    fn assigned_before_read() {
+        assert_eq!(check("a := 1;\nb := a + 1;\nprint(a, b)"), vec![]);
+        assert_eq!(check("a := b + 1;\nprint(a, b)"), vec![uninit("b", 1, 6)]);
+    }
+
+    #[test]
+    fn assignment_value_is_read_before_its_target_is_set() {
+        assert_eq!(check("a := a + 1"), vec![uninit("a", 1, 6)]);
+    }
+
+    #[test]
+    fn eseq_statement_runs_before_its_expression() {
+        assert_eq!(check("x := (y := 2, y + 1);\nprint(x, y)"), vec![]);
+        assert_eq!(check("print(w, (w := 1, w))"), vec![uninit("w", 1, 7)]);
+        assert_eq!(check("x := (y := z, 1)"), vec![uninit("z", 1, 12)]);
+    }
+
+    #[test]
+    fn loop_body_assignment_may_not_have_run_after_the_loop() {
+        let source = "for (i := 0; i < 3; i := i + 1) { y := i };\nprint(y)";
+        assert_eq!(check(source), vec![maybe("y", 2, 7)]);
+        // inside the loop the body's own assignment counts
+        let source = "for (i := 0; i < 3; i := i + 1) { y := i; print(y) }";
+        assert_eq!(check(source), vec![]);
+        // the loop's start always runs
+        assert_eq!(
+            check("for (i := 0; i < 3; i := i + 1) { print(i) };\nprint(i)"),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn read_in_body_before_body_assigns() {
+        let source = "for (i := 0; i < 3; i := i + 1) { print(z); z := i }";
+        assert_eq!(check(source), vec![uninit("z", 1, 41)]);
+    }
+
+    #[test]
+    fn update_only_assignment_read_in_condition() {
+        let source = "for (i := 0; i < k; k := 1) { print(i) }";
+        assert_eq!(check(source), vec![uninit("k", 1, 18)]);
+        let source = "for (i := 0; i < 3; i := i + k) { k := 1 }";
+        assert_eq!(check(source), vec![]);
+    }
+
+    #[test]
+    fn asm_in_operands_are_read_before_out_operands_are_set() {
+        assert_eq!(
+            check("asm(\"move %0, %1\", out(r), in(s));\nprint(r)"),
+            vec![uninit("s", 1, 31)]
+        );
+        assert_eq!(
+            check("asm(\"move %0, %1\", out(r), in(r))"),
+            vec![uninit("r", 1, 31)]
+        );
+        assert_eq!(
+            check("s := 1;\nasm(\"move %0, %1\", out(r), in(s));\nprint(r)"),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn const_counts_as_assigned() {
+        assert_eq!(check("const N := 4;\nx := N * 2;\nprint(x)"), vec![]);
+    }
+
+    #[test]
+    fn each_variable_is_reported_once() {
+        let source =
+            "a := b;\nc := b + d;\nfor (i := 0; i < 1; i := i + 1) { y := 1 };\nprint(y, y, d)";
+        assert_eq!(
+            check(source),
+            vec![uninit("b", 1, 6), uninit("d", 2, 10), maybe("y", 4, 7)]
+        );
+    }
+
+    #[test]
+    fn exported_variable_counts_as_assigned_in_importing_file() {
+        let dir = TempDir::new("semantic");
+        dir.write(
+            "lib.slp",
+            "export v;\nexport u;\nv := 1;\nfor (i := 0; i < 1; i := i + 1) { u := 2 }",
+        );
+        dir.write("main.slp", "import lib;\nprint(lib.v, lib.u)");
+        let modules = dir.load("main.slp");
+
+        let mut init_check = InitCheck::default();
+        let mut reports = Vec::new();
+        for module in &modules {
+            let mut errors = Vec::new();
+            init_check.check_stm(&module.prog, &mut errors);
+            reports.extend(errors.iter().map(describe));
+        }
+        assert_eq!(reports, vec![maybe("lib.u", 2, 14)]);
+    }
+}
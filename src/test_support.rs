@@ -0,0 +1,120 @@
+use std::path::PathBuf;
+
+use crate::ast::{NodeIds, Stm};
+use crate::cst::SyntaxTree;
+use crate::descent::parse_descent;
+use crate::errors::{ParseError, TokenError};
+use crate::grammar::{build_table, ParseTable};
+use crate::lex::Lexer;
+use crate::modules::{load_program, Module};
+use crate::options::ParserKind;
+use crate::parser::parse;
+use crate::preprocess::SourceMap;
+
+// What parsing a string gave back, along with every error found on the way.
+pub struct Parsed<T> {
+    pub prog: T,
+    pub token_errors: Vec<TokenError>,
+    pub errors: Vec<ParseError>,
+}
+
+impl<T> Parsed<T> {
+    // Where each error was found, as (line, col), lexer errors first.
+    pub fn error_positions(&self) -> Vec<(usize, usize)> {
+        let spans = self.token_errors.iter().map(TokenError::span);
+        let spans = spans.chain(self.errors.iter().map(ParseError::span));
+        spans.map(|span| (span.line, span.col)).collect()
+    }
+
+    // The program, which must have parsed without errors.
+    pub fn clean(self, source: &str) -> T {
+        assert!(
+            self.token_errors.is_empty() && self.errors.is_empty(),
+            "{:?} was rejected",
+            source
+        );
+        self.prog
+    }
+}
+
+pub fn table() -> ParseTable {
+    build_table().unwrap_or_else(|_| panic!("grammar has conflicts"))
+}
+
+pub fn parse_str_slr(table: &ParseTable, source: &str) -> Parsed<Option<(SyntaxTree, Stm)>> {
+    let source_map = SourceMap::default();
+    let mut token_errors = Vec::new();
+    let mut errors = Vec::new();
+    let prog = parse(
+        table,
+        &mut Lexer::new(source.as_bytes(), &source_map),
+        source,
+        &mut token_errors,
+        &mut errors,
+        &mut NodeIds::default(),
+        None,
+    );
+    Parsed {
+        prog,
+        token_errors,
+        errors,
+    }
+}
+
+pub fn parse_str_descent(source: &str) -> Parsed<Stm> {
+    let source_map = SourceMap::default();
+    let mut token_errors = Vec::new();
+    let mut errors = Vec::new();
+    let prog = parse_descent(
+        &mut Lexer::new(source.as_bytes(), &source_map),
+        &mut token_errors,
+        &mut errors,
+        &mut NodeIds::default(),
+    );
+    Parsed {
+        prog,
+        token_errors,
+        errors,
+    }
+}
+
+// A scratch directory for tests that need files, removed when dropped so a
+// failing assertion doesn't leave it behind.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!("slp_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+
+    pub fn write(&self, file: &str, contents: &str) {
+        std::fs::write(self.path.join(file), contents).unwrap();
+    }
+
+    pub fn path(&self, file: &str) -> String {
+        self.path.join(file).display().to_string()
+    }
+
+    // The modules of the program rooted at `file`, parsed by descent.
+    pub fn load(&self, file: &str) -> Vec<Module> {
+        load_program(
+            &self.path(file),
+            &[],
+            &table(),
+            None,
+            ParserKind::Descent,
+            false,
+        )
+        .unwrap_or_else(|errors| panic!("{}", errors.join("\n")))
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
@@ -0,0 +1,166 @@
+use crate::ast::NodeId;
+use crate::grammar::{production, TERMINALS};
+use crate::ir_code_gen::SemanticValue;
+use crate::lex::{describe_token, Token};
+
+// How --trace-parse prints the parser's steps.
+#[derive(Clone, Copy)]
+pub enum TraceFormat {
+    Text,
+    JsonLines,
+}
+
+// What the parser did at one step.
+pub enum TraceAction<'a> {
+    Shift(usize),
+    // the production, the state its goto led to and the value gen_line built
+    Reduce(usize, usize, &'a SemanticValue),
+    Accept,
+    Error,
+    // a token dropped because it failed again before anything was shifted
+    Skip,
+    // where the parse picks up after an error; the stack ends in the state that
+    // shifted `error` and the lookahead is the token input was skipped to
+    Recover,
+}
+
+// Prints every step of one file's parse to stderr, which keeps the trace apart
+// from the token stream and assembly on stdout. Each step shows the state stack
+// and lookahead the parser acted on.
+pub struct ParseTrace {
+    format: TraceFormat,
+    file: String,
+    step: usize,
+    // wide enough for every kind of token's description; a longer name, number
+    // or string is cut short
+    lookahead_width: usize,
+}
+
+impl ParseTrace {
+    pub fn new(format: TraceFormat, file: &str) -> ParseTrace {
+        let lookahead_width = TERMINALS
+            .iter()
+            .map(|name| describe_token(name).chars().count())
+            .max()
+            .unwrap_or(0);
+        if let TraceFormat::Text = format {
+            eprintln!("Parse trace of {}:", file);
+            eprintln!(
+                "{:>5}  {:<30}  {:<width$}  {:<18}  {:<36}  value",
+                "step",
+                "stack",
+                "lookahead",
+                "action",
+                "production",
+                width = lookahead_width
+            );
+        }
+        ParseTrace {
+            format,
+            file: file.to_string(),
+            step: 0,
+            lookahead_width,
+        }
+    }
+
+    pub fn record(&mut self, states: &[usize], lookahead: &Token, action: TraceAction) {
+        self.step += 1;
+        match self.format {
+            TraceFormat::Text => self.text_row(states, lookahead, &action),
+            TraceFormat::JsonLines => self.json_line(states, lookahead, &action),
+        }
+    }
+
+    fn text_row(&self, states: &[usize], lookahead: &Token, action: &TraceAction) {
+        let stack: Vec<String> = states.iter().map(|state| state.to_string()).collect();
+        let (action, production, value) = match action {
+            TraceAction::Shift(state) => (format!("shift {}", state), String::new(), String::new()),
+            TraceAction::Reduce(prod, goto, value) => (
+                format!("reduce {}, goto {}", prod, goto),
+                rule(*prod),
+                format!("{} {}", value.kind(), value),
+            ),
+            TraceAction::Accept => (String::from("accept"), String::new(), String::new()),
+            TraceAction::Error => (String::from("error"), String::new(), String::new()),
+            TraceAction::Skip => (String::from("skip"), String::new(), String::new()),
+            TraceAction::Recover => (String::from("recover"), String::new(), String::new()),
+        };
+        let mut lookahead = lookahead.describe();
+        if lookahead.chars().count() > self.lookahead_width {
+            lookahead = lookahead.chars().take(self.lookahead_width - 1).collect();
+            lookahead.push('…');
+        }
+        let row = format!(
+            "{:>5}  {:<30}  {:<width$}  {:<18}  {:<36}  {}",
+            self.step,
+            stack.join(" "),
+            lookahead,
+            action,
+            production,
+            value,
+            width = self.lookahead_width
+        );
+        eprintln!("{}", row.trim_end());
+    }
+
+    fn json_line(&self, states: &[usize], lookahead: &Token, action: &TraceAction) {
+        let stack: Vec<String> = states.iter().map(|state| state.to_string()).collect();
+        let mut line = format!(
+            "{{\"file\":{},\"step\":{},\"stack\":[{}],\"lookahead\":{},\"text\":{}",
+            json_string(&self.file),
+            self.step,
+            stack.join(","),
+            json_string(lookahead.name()),
+            json_string(&lookahead.val())
+        );
+        let fields = match action {
+            TraceAction::Shift(state) => format!(",\"action\":\"shift\",\"state\":{}", state),
+            TraceAction::Reduce(prod, goto, value) => {
+                let mut fields = format!(
+                    ",\"action\":\"reduce\",\"production\":{},\"rule\":{},\"goto\":{},\"kind\":{},\"value\":{}",
+                    prod,
+                    json_string(&rule(*prod)),
+                    goto,
+                    json_string(value.kind()),
+                    json_string(&value.to_string())
+                );
+                if let Some(NodeId(node)) = value.node_id() {
+                    fields.push_str(&format!(",\"node\":{}", node));
+                }
+                fields
+            }
+            TraceAction::Accept => String::from(",\"action\":\"accept\""),
+            TraceAction::Error => String::from(",\"action\":\"error\""),
+            TraceAction::Skip => String::from(",\"action\":\"skip\""),
+            TraceAction::Recover => String::from(",\"action\":\"recover\""),
+        };
+        line.push_str(&fields);
+        line.push('}');
+        eprintln!("{}", line);
+    }
+}
+
+// "lhs -> rhs" of a production, as written in grammar::PRODUCTIONS.
+fn rule(prod: usize) -> String {
+    match production(prod) {
+        Some(p) => format!("{} -> {}", p.lhs, p.rhs.join(" ")),
+        None => format!("production {}", prod),
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}